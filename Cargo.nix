@@ -694,6 +694,7 @@ in
     dependencies = {
       inflector = rustPackages."registry+https://github.com/rust-lang/crates.io-index".Inflector."0.11.4" { inherit profileName; };
       anyhow = rustPackages."registry+https://github.com/rust-lang/crates.io-index".anyhow."1.0.38" { inherit profileName; };
+      atty = rustPackages."registry+https://github.com/rust-lang/crates.io-index".atty."0.2.14" { inherit profileName; };
       chrono = rustPackages."registry+https://github.com/rust-lang/crates.io-index".chrono."0.4.19" { inherit profileName; };
       clap = rustPackages."git+https://github.com/clap-rs/clap".clap."3.0.0-beta.2" { inherit profileName; };
       duplicate = buildRustPackages."registry+https://github.com/rust-lang/crates.io-index".duplicate."0.2.9" { profileName = "__noProfile"; };
@@ -701,6 +702,8 @@ in
       fieldpath = rustPackages."unknown".fieldpath."0.1.0" { inherit profileName; };
       futures = rustPackages."registry+https://github.com/rust-lang/crates.io-index".futures."0.3.12" { inherit profileName; };
       http = rustPackages."registry+https://github.com/rust-lang/crates.io-index".http."0.2.3" { inherit profileName; };
+      hyper = rustPackages."registry+https://github.com/rust-lang/crates.io-index".hyper."0.14.2" { inherit profileName; };
+      hyper_tls = rustPackages."registry+https://github.com/rust-lang/crates.io-index".hyper-tls."0.5.0" { inherit profileName; };
       jrsonnet_cli = rustPackages."git+https://github.com/CertainLach/jrsonnet.git".jrsonnet-cli."0.3.5" { inherit profileName; };
       jrsonnet_evaluator = rustPackages."git+https://github.com/CertainLach/jrsonnet.git".jrsonnet-evaluator."0.3.5" { inherit profileName; };
       jrsonnet_interner = rustPackages."git+https://github.com/CertainLach/jrsonnet.git".jrsonnet-interner."0.3.5" { inherit profileName; };
@@ -710,7 +713,9 @@ in
       k8s_openapi = rustPackages."registry+https://github.com/rust-lang/crates.io-index".k8s-openapi."0.11.0" { inherit profileName; };
       kube = rustPackages."registry+https://github.com/rust-lang/crates.io-index".kube."0.51.0" { inherit profileName; };
       kube_derive = buildRustPackages."registry+https://github.com/rust-lang/crates.io-index".kube-derive."0.51.0" { profileName = "__noProfile"; };
+      lazy_static = rustPackages."registry+https://github.com/rust-lang/crates.io-index".lazy_static."1.4.0" { inherit profileName; };
       log = rustPackages."registry+https://github.com/rust-lang/crates.io-index".log."0.4.14" { inherit profileName; };
+      md5 = rustPackages."registry+https://github.com/rust-lang/crates.io-index".md5."0.7.0" { inherit profileName; };
       peg = rustPackages."registry+https://github.com/rust-lang/crates.io-index".peg."0.6.3" { inherit profileName; };
       rustc_hash = rustPackages."registry+https://github.com/rust-lang/crates.io-index".rustc-hash."1.1.0" { inherit profileName; };
       serde = rustPackages."registry+https://github.com/rust-lang/crates.io-index".serde."1.0.123" { inherit profileName; };
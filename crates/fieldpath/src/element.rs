@@ -23,7 +23,7 @@ impl Display for Element {
             Self::StaticField(field) => write_field(f, field),
             Self::Field(field) => write_field(f, field),
             Self::Select(key, value) => write!(f, "[{}={}]", key, value),
-            Self::Index(idx) => write!(f, "{}", idx),
+            Self::Index(idx) => write!(f, "[{}]", idx),
         }
     }
 }
@@ -0,0 +1,190 @@
+use super::{
+    discovery::Discovery,
+    find::{api_group, Object, RuntimeTypeData},
+    Error, Result,
+};
+use futures::{stream, StreamExt};
+use kube::Client;
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeSet,
+    fmt::{self, Display},
+};
+
+const REVIEW_CONCURRENCY: usize = 16;
+
+/// Action, which hayasaka needs to perform
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Access {
+    pub verb: &'static str,
+    pub group: String,
+    /// Plural resource name
+    pub resource: String,
+    /// None for cluster-wide access
+    pub namespace: Option<String>,
+}
+
+impl Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.verb, self.resource)?;
+        if !self.group.is_empty() {
+            write!(f, ".{}", self.group)?;
+        }
+        if let Some(namespace) = &self.namespace {
+            write!(f, " in {}", namespace)?;
+        }
+        Ok(())
+    }
+}
+
+async fn is_allowed(client: Client, access: &Access) -> Result<bool> {
+    let req = http::Request::post("/apis/authorization.k8s.io/v1/selfsubjectaccessreviews")
+        .header("Accept", "application/json")
+        .header("Content-Type", "application/json")
+        .body(serde_json::to_vec(&json!({
+            "apiVersion": "authorization.k8s.io/v1",
+            "kind": "SelfSubjectAccessReview",
+            "spec": {
+                "resourceAttributes": {
+                    "verb": access.verb,
+                    "group": access.group,
+                    "resource": access.resource,
+                    "namespace": access.namespace,
+                },
+            },
+        }))?)
+        .map_err(kube::Error::HttpError)?;
+    let review: Value = client.request(req).await?;
+    Ok(review["status"]["allowed"].as_bool().unwrap_or(false))
+}
+
+async fn find_denied(client: Client, accesses: BTreeSet<Access>) -> Result<Vec<Access>> {
+    let reviews = stream::iter(accesses)
+        .map(|access| {
+            let client = client.clone();
+            async move {
+                let allowed = is_allowed(client, &access).await;
+                (access, allowed)
+            }
+        })
+        .buffer_unordered(REVIEW_CONCURRENCY)
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut out = Vec::new();
+    for (access, allowed) in reviews {
+        if !allowed? {
+            out.push(access);
+        }
+    }
+    out.sort();
+    Ok(out)
+}
+
+fn join(accesses: &[&Access]) -> String {
+    accesses
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Access, needed to apply target and prune leftovers
+///
+/// Prune lists every kind, but only deletes objects of rendered kinds in their namespaces,
+/// objects of kinds removed from deployment should be pruned by someone else
+pub fn required_access(
+    discovery: &Discovery,
+    types: &RuntimeTypeData,
+    target: &[(Object, Value)],
+    prune: bool,
+) -> BTreeSet<Access> {
+    let mut required = BTreeSet::new();
+    for (object, _) in target {
+        let data = match types.get(&object.kind) {
+            Some(data) => data,
+            None => continue,
+        };
+        let verbs: &[&'static str] = if prune {
+            &["get", "patch", "delete"]
+        } else {
+            &["get", "patch"]
+        };
+        for &verb in verbs {
+            required.insert(Access {
+                verb,
+                group: object.kind.group().to_owned(),
+                resource: data.plural.clone(),
+                namespace: object.metadata.namespace.clone(),
+            });
+        }
+    }
+    if prune {
+        for resource in discovery
+            .resources
+            .iter()
+            .filter(|r| r.preferred && r.verbs.iter().any(|v| v == "list"))
+        {
+            required.insert(Access {
+                verb: "list",
+                group: api_group(&resource.api_version).to_owned(),
+                resource: resource.plural.clone(),
+                namespace: None,
+            });
+        }
+    }
+    required
+}
+
+/// Check, that current user is able to apply target and prune leftovers, before changing anything
+///
+/// Denied list of prune scope is only an error in strict mode, otherwise objects of this kind
+/// are silently left behind. In strict mode failure to check access is an error too
+pub async fn check_access(
+    client: Client,
+    discovery: &Discovery,
+    types: &RuntimeTypeData,
+    target: &[(Object, Value)],
+    prune: bool,
+    strict: bool,
+) -> Result<()> {
+    let required = required_access(discovery, types, target, prune);
+    let denied = match find_denied(client, required).await {
+        Ok(denied) => denied,
+        Err(e) if strict => return Err(e),
+        Err(e) => {
+            log::warn!("failed to check access, skipping preflight: {}", e);
+            return Ok(());
+        }
+    };
+    let by_verb = |verbs: &[&str]| {
+        denied
+            .iter()
+            .filter(|a| verbs.contains(&a.verb))
+            .collect::<Vec<_>>()
+    };
+
+    let denied_delete = by_verb(&["delete"]);
+    if !denied_delete.is_empty() {
+        log::warn!(
+            "no access to {}, such objects can't be pruned",
+            join(&denied_delete)
+        );
+    }
+    let denied_list = by_verb(&["list"]);
+    if !denied_list.is_empty() && !strict {
+        log::warn!(
+            "no access to {}, assuming there should be no such objects deployed",
+            join(&denied_list)
+        );
+    }
+
+    let mut fatal = by_verb(&["get", "patch"]);
+    if strict {
+        fatal.extend(denied_list);
+    }
+    if !fatal.is_empty() {
+        return Err(Error::AccessDenied(join(&fatal)));
+    }
+    Ok(())
+}
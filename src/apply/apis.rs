@@ -0,0 +1,203 @@
+use super::{discovery::Discovery, find::api_group, Result};
+use kube::Client;
+use serde_json::Value;
+
+/// Kubernetes minor version, i.e `(1, 22)`
+pub type Version = (u32, u32);
+
+/// Api version of object kind, which is deprecated in favor of another one
+struct ApiMove {
+    api_version: &'static str,
+    kind: &'static str,
+    /// Api version, which should be used instead
+    replacement: Option<&'static str>,
+    deprecated_in: Version,
+    removed_in: Option<Version>,
+    /// Can object be converted just by changing its apiVersion
+    is_safe: fn(&Value) -> bool,
+}
+
+fn always(_: &Value) -> bool {
+    true
+}
+fn never(_: &Value) -> bool {
+    false
+}
+/// Apps objects lost selector defaulting in apps/v1
+fn has_selector(value: &Value) -> bool {
+    value["spec"]["selector"].is_object()
+}
+
+macro_rules! moves {
+    ($($api_version:literal $kind:ident => $replacement:expr, $deprecated:expr, $removed:expr, $safe:ident;)*) => {
+        &[$(ApiMove {
+            api_version: $api_version,
+            kind: stringify!($kind),
+            replacement: $replacement,
+            deprecated_in: $deprecated,
+            removed_in: $removed,
+            is_safe: $safe,
+        },)*]
+    };
+}
+
+static MOVES: &[ApiMove] = moves! {
+    "extensions/v1beta1" Ingress => Some("networking.k8s.io/v1beta1"), (1, 14), Some((1, 22)), always;
+    "networking.k8s.io/v1beta1" Ingress => Some("networking.k8s.io/v1"), (1, 19), Some((1, 22)), never;
+    "networking.k8s.io/v1beta1" IngressClass => Some("networking.k8s.io/v1"), (1, 19), Some((1, 22)), always;
+    "extensions/v1beta1" NetworkPolicy => Some("networking.k8s.io/v1"), (1, 9), Some((1, 16)), always;
+    "extensions/v1beta1" PodSecurityPolicy => Some("policy/v1beta1"), (1, 10), Some((1, 16)), always;
+    "policy/v1beta1" PodSecurityPolicy => None, (1, 21), Some((1, 25)), never;
+    "policy/v1beta1" PodDisruptionBudget => Some("policy/v1"), (1, 21), Some((1, 25)), never;
+    "extensions/v1beta1" Deployment => Some("apps/v1"), (1, 9), Some((1, 16)), has_selector;
+    "extensions/v1beta1" DaemonSet => Some("apps/v1"), (1, 9), Some((1, 16)), has_selector;
+    "extensions/v1beta1" ReplicaSet => Some("apps/v1"), (1, 9), Some((1, 16)), has_selector;
+    "apps/v1beta1" Deployment => Some("apps/v1"), (1, 9), Some((1, 16)), has_selector;
+    "apps/v1beta1" StatefulSet => Some("apps/v1"), (1, 9), Some((1, 16)), has_selector;
+    "apps/v1beta2" Deployment => Some("apps/v1"), (1, 9), Some((1, 16)), always;
+    "apps/v1beta2" StatefulSet => Some("apps/v1"), (1, 9), Some((1, 16)), always;
+    "apps/v1beta2" DaemonSet => Some("apps/v1"), (1, 9), Some((1, 16)), always;
+    "apps/v1beta2" ReplicaSet => Some("apps/v1"), (1, 9), Some((1, 16)), always;
+    "batch/v1beta1" CronJob => Some("batch/v1"), (1, 21), Some((1, 25)), always;
+    "autoscaling/v2beta1" HorizontalPodAutoscaler => Some("autoscaling/v2"), (1, 22), Some((1, 25)), never;
+    "autoscaling/v2beta2" HorizontalPodAutoscaler => Some("autoscaling/v2"), (1, 23), Some((1, 26)), always;
+    "rbac.authorization.k8s.io/v1beta1" Role => Some("rbac.authorization.k8s.io/v1"), (1, 17), Some((1, 22)), always;
+    "rbac.authorization.k8s.io/v1beta1" ClusterRole => Some("rbac.authorization.k8s.io/v1"), (1, 17), Some((1, 22)), always;
+    "rbac.authorization.k8s.io/v1beta1" RoleBinding => Some("rbac.authorization.k8s.io/v1"), (1, 17), Some((1, 22)), always;
+    "rbac.authorization.k8s.io/v1beta1" ClusterRoleBinding => Some("rbac.authorization.k8s.io/v1"), (1, 17), Some((1, 22)), always;
+    "scheduling.k8s.io/v1beta1" PriorityClass => Some("scheduling.k8s.io/v1"), (1, 14), Some((1, 22)), always;
+    "coordination.k8s.io/v1beta1" Lease => Some("coordination.k8s.io/v1"), (1, 14), Some((1, 22)), always;
+    "apiextensions.k8s.io/v1beta1" CustomResourceDefinition => Some("apiextensions.k8s.io/v1"), (1, 16), Some((1, 22)), never;
+    "admissionregistration.k8s.io/v1beta1" MutatingWebhookConfiguration => Some("admissionregistration.k8s.io/v1"), (1, 16), Some((1, 22)), never;
+    "admissionregistration.k8s.io/v1beta1" ValidatingWebhookConfiguration => Some("admissionregistration.k8s.io/v1"), (1, 16), Some((1, 22)), never;
+    "discovery.k8s.io/v1beta1" EndpointSlice => Some("discovery.k8s.io/v1"), (1, 21), Some((1, 25)), never;
+};
+
+/// Group, which now serves objects of this kind, i.e `networking.k8s.io` for `extensions` Ingress
+///
+/// Objects are the same regardless of group they were accessed through
+pub fn canonical_group<'a>(group_name: &'a str, kind: &str) -> &'a str {
+    MOVES
+        .iter()
+        .filter(|m| m.kind == kind && api_group(m.api_version) == group_name)
+        .filter_map(|m| m.replacement.map(api_group))
+        .find(|replacement| *replacement != group_name)
+        .map(|replacement| canonical_group(replacement, kind))
+        .unwrap_or(group_name)
+}
+
+fn parse_version(major: &str, minor: &str) -> Option<Version> {
+    // Minor version may have suffix, i.e `20+` on GKE
+    let digits = |s: &str| {
+        s.chars()
+            .take_while(char::is_ascii_digit)
+            .collect::<String>()
+            .parse()
+            .ok()
+    };
+    Some((digits(major)?, digits(minor)?))
+}
+
+pub async fn server_version(client: Client) -> Result<Option<Version>> {
+    let info = client.apiserver_version().await?;
+    Ok(parse_version(&info.major, &info.minor))
+}
+
+fn format_version(version: Version) -> String {
+    format!("{}.{}", version.0, version.1)
+}
+
+/// Warn about deprecated and removed apis used by object, and convert it to replacement
+/// api if this is safe, and replacement is served by cluster
+pub fn upgrade(item: &mut Value, server: Option<Version>, discovery: &Discovery) {
+    let name = item["metadata"]["name"].as_str().unwrap_or("").to_owned();
+    while let Some(api_move) = MOVES
+        .iter()
+        .find(|m| item["apiVersion"] == m.api_version && item["kind"] == m.kind)
+    {
+        let object = format!("{} {} {}", api_move.api_version, api_move.kind, name);
+        let is_removed = match (server, api_move.removed_in) {
+            (Some(server), Some(removed)) => server >= removed,
+            _ => false,
+        };
+        let is_deprecated = server.map(|s| s >= api_move.deprecated_in).unwrap_or(true);
+
+        let replacement = match api_move.replacement {
+            Some(r) if (api_move.is_safe)(item) && discovery.serves(r, api_move.kind) => r,
+            _ => {
+                let replacement = api_move
+                    .replacement
+                    .map(|r| format!(", use {} instead", r))
+                    .unwrap_or_default();
+                if is_removed {
+                    log::warn!(
+                        "{} uses api removed in {}{}",
+                        object,
+                        format_version(api_move.removed_in.unwrap()),
+                        replacement
+                    );
+                } else if is_deprecated {
+                    log::warn!(
+                        "{} uses api deprecated in {}{}",
+                        object,
+                        format_version(api_move.deprecated_in),
+                        replacement
+                    );
+                }
+                return;
+            }
+        };
+        log::warn!(
+            "{} uses outdated api, converting to {}",
+            object,
+            replacement
+        );
+        item["apiVersion"] = Value::String(replacement.to_owned());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn groups() {
+        assert_eq!(
+            canonical_group("extensions", "Ingress"),
+            "networking.k8s.io"
+        );
+        assert_eq!(canonical_group("extensions", "Deployment"), "apps");
+        assert_eq!(canonical_group("apps", "Deployment"), "apps");
+        assert_eq!(canonical_group("extensions", "Unknown"), "extensions");
+    }
+
+    #[test]
+    fn versions() {
+        assert_eq!(parse_version("1", "20+"), Some((1, 20)));
+        assert_eq!(parse_version("1", ""), None);
+    }
+
+    #[test]
+    fn conversion() {
+        let discovery = Discovery::from_kinds(&[
+            ("networking.k8s.io/v1beta1", "Ingress"),
+            ("networking.k8s.io/v1", "Ingress"),
+            ("batch/v1", "CronJob"),
+        ]);
+
+        // Converted to v1beta1, but not to v1, because schema has changed
+        let mut item = json!({"apiVersion": "extensions/v1beta1", "kind": "Ingress"});
+        upgrade(&mut item, Some((1, 19)), &discovery);
+        assert_eq!(item["apiVersion"], "networking.k8s.io/v1beta1");
+
+        let mut item = json!({"apiVersion": "batch/v1beta1", "kind": "CronJob"});
+        upgrade(&mut item, Some((1, 21)), &discovery);
+        assert_eq!(item["apiVersion"], "batch/v1");
+
+        // Replacement is not served
+        let mut item = json!({"apiVersion": "apps/v1beta2", "kind": "Deployment"});
+        upgrade(&mut item, Some((1, 15)), &discovery);
+        assert_eq!(item["apiVersion"], "apps/v1beta2");
+    }
+}
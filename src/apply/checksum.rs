@@ -0,0 +1,182 @@
+use super::find::Object;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// ConfigMap or Secret reference, (kind, name)
+type ConfigRef = (&'static str, String);
+
+fn pod_template_mut<'v>(object: &Object, value: &'v mut Value) -> Option<&'v mut Value> {
+    if object.kind.api_version != "apps/v1" && !object.kind.api_version.starts_with("batch/") {
+        return None;
+    }
+    match object.kind.kind.as_str() {
+        "Deployment" | "StatefulSet" | "DaemonSet" | "ReplicaSet" => {
+            Some(&mut value["spec"]["template"])
+        }
+        "CronJob" => Some(&mut value["spec"]["jobTemplate"]["spec"]["template"]),
+        // Job pod template is immutable
+        _ => None,
+    }
+}
+
+fn push_ref(out: &mut BTreeSet<ConfigRef>, kind: &'static str, name: &Value) {
+    if let Some(name) = name.as_str() {
+        out.insert((kind, name.to_owned()));
+    }
+}
+
+/// Collect ConfigMaps and Secrets referenced by pod spec
+fn pod_references(spec: &Value) -> BTreeSet<ConfigRef> {
+    let mut out = BTreeSet::new();
+    let empty = vec![];
+
+    for volume in spec["volumes"].as_array().unwrap_or(&empty) {
+        push_ref(&mut out, "ConfigMap", &volume["configMap"]["name"]);
+        push_ref(&mut out, "Secret", &volume["secret"]["secretName"]);
+        for source in volume["projected"]["sources"].as_array().unwrap_or(&empty) {
+            push_ref(&mut out, "ConfigMap", &source["configMap"]["name"]);
+            push_ref(&mut out, "Secret", &source["secret"]["name"]);
+        }
+    }
+
+    let containers = spec["containers"].as_array().unwrap_or(&empty);
+    let init_containers = spec["initContainers"].as_array().unwrap_or(&empty);
+    for container in containers.iter().chain(init_containers.iter()) {
+        for env_from in container["envFrom"].as_array().unwrap_or(&empty) {
+            push_ref(&mut out, "ConfigMap", &env_from["configMapRef"]["name"]);
+            push_ref(&mut out, "Secret", &env_from["secretRef"]["name"]);
+        }
+        for env in container["env"].as_array().unwrap_or(&empty) {
+            push_ref(
+                &mut out,
+                "ConfigMap",
+                &env["valueFrom"]["configMapKeyRef"]["name"],
+            );
+            push_ref(&mut out, "Secret", &env["valueFrom"]["secretKeyRef"]["name"]);
+        }
+    }
+
+    out
+}
+
+/// Feed object data into hash in stable order
+fn consume_data(context: &mut md5::Context, value: &Value) {
+    for field in ["data", "binaryData", "stringData"].iter() {
+        if let Some(data) = value[field].as_object() {
+            let sorted = data.iter().collect::<BTreeMap<_, _>>();
+            for (key, value) in sorted {
+                context.consume(field);
+                context.consume(b"/");
+                context.consume(key);
+                context.consume(b"=");
+                context.consume(value.to_string());
+                context.consume(b"\n");
+            }
+        }
+    }
+}
+
+/// Annotate pod templates of workloads with checksum of rendered ConfigMaps and Secrets
+/// they reference, so that change of their content triggers rollout
+///
+/// Referenced objects, which are not part of deployment, are not included in checksum
+pub fn inject_config_checksums(target: &mut [(Object, Value)], annotation: &str) {
+    let mut configs = BTreeMap::new();
+    for (object, value) in target.iter() {
+        if object.kind.api_version == "v1"
+            && (object.kind.kind == "ConfigMap" || object.kind.kind == "Secret")
+        {
+            configs.insert(
+                (
+                    object.metadata.namespace.clone(),
+                    object.kind.kind.clone(),
+                    object.metadata.name.clone(),
+                ),
+                value.clone(),
+            );
+        }
+    }
+
+    for (object, value) in target.iter_mut() {
+        let template = match pod_template_mut(object, value) {
+            Some(t) if t.is_object() => t,
+            _ => continue,
+        };
+
+        let mut context = md5::Context::new();
+        let mut found = false;
+        for (kind, name) in pod_references(&template["spec"]) {
+            let key = (object.metadata.namespace.clone(), kind.to_owned(), name);
+            if let Some(config) = configs.get(&key) {
+                context.consume(format!("{} {}\n", key.1, key.2));
+                consume_data(&mut context, config);
+                found = true;
+            }
+        }
+        if !found {
+            continue;
+        }
+
+        let checksum = format!("{:x}", context.compute());
+        log::debug!("{} config checksum is {}", object, checksum);
+        let metadata = &mut template["metadata"];
+        if metadata.is_null() {
+            *metadata = Value::Object(Default::default());
+        }
+        let annotations = &mut metadata["annotations"];
+        if annotations.is_null() {
+            *annotations = Value::Object(Default::default());
+        }
+        annotations[annotation] = Value::String(checksum);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::find::tests::object;
+    use serde_json::json;
+
+    fn deployment() -> Value {
+        json!({
+            "spec": {"template": {"spec": {
+                "volumes": [{"name": "config", "configMap": {"name": "app"}}],
+                "containers": [{
+                    "name": "app",
+                    "env": [{
+                        "name": "PASSWORD",
+                        "valueFrom": {"secretKeyRef": {"name": "credentials", "key": "password"}},
+                    }],
+                }],
+            }}},
+        })
+    }
+
+    #[test]
+    fn references() {
+        let refs = pod_references(&deployment()["spec"]["template"]["spec"]);
+        assert_eq!(
+            refs.into_iter().collect::<Vec<_>>(),
+            vec![
+                ("ConfigMap", "app".to_owned()),
+                ("Secret", "credentials".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn checksum_follows_data() {
+        let checksum = |data: &str| {
+            let mut target = vec![
+                (object("v1", "ConfigMap", "app"), json!({"data": {"key": data}})),
+                (object("apps/v1", "Deployment", "app"), deployment()),
+            ];
+            inject_config_checksums(&mut target, "checksum");
+            target[1].1["spec"]["template"]["metadata"]["annotations"]["checksum"].clone()
+        };
+
+        assert!(checksum("a").is_string());
+        assert_eq!(checksum("a"), checksum("a"));
+        assert_ne!(checksum("a"), checksum("b"));
+    }
+}
@@ -0,0 +1,90 @@
+use super::{
+    discovery::Discovery,
+    exclude::{is_excluded, GroupKind},
+    find, get, make_url,
+    order::install_order,
+    remove, Error, Result,
+};
+use find::{Object, RuntimeTypeData};
+use kube::Client;
+use std::{
+    collections::BTreeSet,
+    time::{Duration, Instant},
+};
+use tokio::time::sleep;
+
+/// Find all objects of deployment, excluding objects generated by controllers
+pub async fn find_deployed(
+    client: Client,
+    discovery: &Discovery,
+    label: (&str, &str),
+    excluded: &[GroupKind],
+) -> Result<BTreeSet<Object>> {
+    Ok(find::find_all_labeled_items(client, discovery, label)
+        .await?
+        .into_iter()
+        .filter(|item| !is_excluded(&item.kind, excluded))
+        .collect())
+}
+
+/// Wait until object is removed, object may be kept by finalizers
+async fn wait_removed(
+    client: Client,
+    object: &Object,
+    types: &RuntimeTypeData,
+    deadline: Instant,
+) -> Result<()> {
+    let url = make_url("", object, types);
+    let mut logged = false;
+    loop {
+        if get(client.clone(), &url).await?.is_none() {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            return Err(Error::RemoveTimeout(object.clone()));
+        }
+        if !logged {
+            log::info!("waiting for {} to be removed", object);
+            logged = true;
+        }
+        sleep(Duration::from_secs(1)).await;
+    }
+}
+
+/// Remove objects in reverse install order, waiting for every kind to be fully removed
+/// before removing objects it may depend on
+pub async fn destroy_multi(
+    client: Client,
+    discovery: &Discovery,
+    objects: BTreeSet<Object>,
+    timeout: Duration,
+) -> Result<()> {
+    let types = discovery.types();
+    let deadline = Instant::now() + timeout;
+
+    let mut objects = objects.into_iter().collect::<Vec<_>>();
+    objects.sort_by_key(|item| std::cmp::Reverse(install_order(&item.kind)));
+    let mut objects = objects.into_iter().peekable();
+
+    while let Some(first) = objects.next() {
+        let order = install_order(&first.kind);
+        let mut tier = vec![first];
+        while let Some(item) = objects.next_if(|item| install_order(&item.kind) == order) {
+            tier.push(item);
+        }
+
+        for item in tier.iter() {
+            log::warn!("removing {}", item);
+            match remove(client.clone(), item, &types).await {
+                Ok(()) => {}
+                Err(Error::Kube(kube::Error::Api(apierror))) if apierror.code == 404 => {}
+                Err(e) => return Err(e),
+            }
+        }
+        for item in tier.iter() {
+            wait_removed(client.clone(), item, &types, deadline).await?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,71 @@
+use fieldpath::{Element, PathBuf};
+use json_patch::PatchOperation;
+use serde_json::Value;
+use std::fmt::Write;
+
+/// Convert json pointer to path, array items are addressed by index
+fn pointer_to_path(value: &Value, pointer: &str) -> PathBuf {
+    let mut out = Vec::new();
+    let mut current = Some(value);
+    for part in pointer.split('/').skip(1) {
+        let part = part.replace("~1", "/").replace("~0", "~");
+        match (current, part.parse::<usize>()) {
+            (Some(Value::Array(items)), Ok(idx)) => {
+                current = items.get(idx);
+                out.push(Element::Index(idx));
+            }
+            _ => {
+                current = current.and_then(|v| v.get(&part));
+                out.push(Element::Field(part));
+            }
+        }
+    }
+    PathBuf(out)
+}
+
+/// Human readable difference between two values, one change per line
+///
+/// Every line is prefixed with newline, empty string is returned for equal values.
+/// Values are printed as is, objects should be passed through [`super::redacted`] first
+pub fn format_diff(a: &Value, b: &Value) -> String {
+    let mut out = String::new();
+    for op in json_patch::diff(a, b).0 {
+        match op {
+            PatchOperation::Add(add) => write!(out, "\n+ {}", pointer_to_path(b, &add.path)),
+            PatchOperation::Remove(remove) => {
+                write!(out, "\n- {}", pointer_to_path(a, &remove.path))
+            }
+            PatchOperation::Replace(replace) => write!(
+                out,
+                "\nr {}\n  - {}\n  + {}",
+                pointer_to_path(a, &replace.path),
+                a.pointer(&replace.path).unwrap(),
+                b.pointer(&replace.path).unwrap()
+            ),
+            _ => unreachable!(),
+        }
+        .unwrap()
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn changes() {
+        let a = json!({"data": {"a": "1", "b": "2"}});
+        let b = json!({"data": {"a": "3", "c": "4"}});
+        assert_eq!(
+            format_diff(&a, &b),
+            "\nr .data.a\n  - \"1\"\n  + \"3\"\n+ .data.c\n- .data.b"
+        );
+        assert_eq!(format_diff(&a, &a), "");
+
+        let a = json!({"ports": [{"port": 80}]});
+        let b = json!({"ports": [{"port": 8080}]});
+        assert_eq!(format_diff(&a, &b), "\nr .ports[0].port\n  - 80\n  + 8080");
+    }
+}
@@ -0,0 +1,242 @@
+use super::{
+    find::{ObjectData, ObjectKind, RuntimeTypeData},
+    Result,
+};
+use kube::Client;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+/// Same as kubectl discovery cache TTL
+const CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Discovered resource
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Resource {
+    #[serde(rename = "apiVersion")]
+    pub api_version: String,
+    pub kind: String,
+    pub plural: String,
+    pub namespaced: bool,
+    pub verbs: Vec<String>,
+    pub is_core: bool,
+    /// Is this version preferred version of group
+    pub preferred: bool,
+}
+
+impl Resource {
+    /// i.e `/apis/apps/v1/deployments`
+    pub fn url(&self) -> String {
+        format!(
+            "/{}/{}/{}",
+            if self.is_core { "api" } else { "apis" },
+            self.api_version,
+            self.plural
+        )
+    }
+}
+
+/// Resources, served by cluster
+pub struct Discovery {
+    pub resources: Vec<Resource>,
+    cache: Option<PathBuf>,
+    /// Resources were loaded from cache, and may be outdated
+    cached: bool,
+}
+
+/// Cache location for cluster in home directory, similar to kubectl `~/.kube/cache/discovery/<host>`
+pub fn cache_path(home: &Path, cluster_url: &str) -> PathBuf {
+    let key = cluster_url
+        .trim_end_matches('/')
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    home.join(".kube/cache/hayasaka")
+        .join(format!("{}.json", key))
+}
+
+fn read_cache(path: &Path) -> Option<Vec<Resource>> {
+    let age = fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())?;
+    if age > CACHE_TTL {
+        log::debug!("discovery cache is expired");
+        return None;
+    }
+    match serde_json::from_slice(&fs::read(path).ok()?) {
+        Ok(resources) => Some(resources),
+        Err(e) => {
+            log::warn!("failed to parse discovery cache: {}", e);
+            None
+        }
+    }
+}
+
+fn write_cache(path: &Path, resources: &[Resource]) {
+    let result = path
+        .parent()
+        .map(fs::create_dir_all)
+        .transpose()
+        .and_then(|_| fs::write(path, serde_json::to_vec(resources)?));
+    if let Err(e) = result {
+        log::warn!("failed to write discovery cache: {}", e);
+    }
+}
+
+async fn fetch(client: Client) -> Result<Vec<Resource>> {
+    let mut out = Vec::new();
+
+    for version in client.list_core_api_versions().await?.versions {
+        for resource in client.list_core_api_resources(&version).await?.resources {
+            if resource.name.contains('/') {
+                continue;
+            }
+            out.push(Resource {
+                api_version: version.clone(),
+                kind: resource.kind,
+                plural: resource.name,
+                namespaced: resource.namespaced,
+                verbs: resource.verbs,
+                is_core: true,
+                preferred: true,
+            });
+        }
+    }
+
+    for group in client.list_api_groups().await?.groups {
+        let preferred = group
+            .preferred_version
+            .as_ref()
+            .or_else(|| group.versions.last())
+            .map(|v| v.group_version.clone());
+        for version in group.versions {
+            for resource in client
+                .list_api_group_resources(&version.group_version)
+                .await?
+                .resources
+            {
+                if resource.name.contains('/') {
+                    continue;
+                }
+                out.push(Resource {
+                    api_version: version.group_version.clone(),
+                    kind: resource.kind,
+                    plural: resource.name,
+                    namespaced: resource.namespaced,
+                    verbs: resource.verbs,
+                    is_core: false,
+                    preferred: preferred.as_ref() == Some(&version.group_version),
+                });
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+impl Discovery {
+    /// Load resources from cache if it is fresh, or from cluster otherwise
+    pub async fn load(client: Client, cache: Option<PathBuf>, refresh: bool) -> Result<Self> {
+        if let (Some(path), false) = (&cache, refresh) {
+            if let Some(resources) = read_cache(path) {
+                log::debug!("using discovery cache {}", path.display());
+                return Ok(Self {
+                    resources,
+                    cache,
+                    cached: true,
+                });
+            }
+        }
+        let mut discovery = Self {
+            resources: vec![],
+            cache,
+            cached: false,
+        };
+        discovery.refresh(client).await?;
+        Ok(discovery)
+    }
+
+    /// Load resources from cluster, and update cache
+    pub async fn refresh(&mut self, client: Client) -> Result<()> {
+        self.resources = fetch(client).await?;
+        self.cached = false;
+        if let Some(path) = &self.cache {
+            write_cache(path, &self.resources);
+        }
+        Ok(())
+    }
+
+    pub fn is_cached(&self) -> bool {
+        self.cached
+    }
+
+    /// Is exactly this version of object kind served by cluster
+    pub fn serves(&self, api_version: &str, kind: &str) -> bool {
+        self.resources
+            .iter()
+            .any(|r| r.api_version == api_version && r.kind == kind)
+    }
+
+    #[cfg(test)]
+    pub fn from_kinds(kinds: &[(&str, &str)]) -> Self {
+        Self {
+            resources: kinds
+                .iter()
+                .map(|(api_version, kind)| Resource {
+                    api_version: api_version.to_string(),
+                    kind: kind.to_string(),
+                    plural: format!("{}s", kind.to_lowercase()),
+                    namespaced: true,
+                    verbs: vec![],
+                    is_core: !api_version.contains('/'),
+                    preferred: true,
+                })
+                .collect(),
+            cache: None,
+            cached: false,
+        }
+    }
+
+    /// All defined object kinds with additional meta
+    pub fn types(&self) -> RuntimeTypeData {
+        let mut out = BTreeMap::new();
+        for resource in &self.resources {
+            out.insert(
+                ObjectKind {
+                    api_version: resource.api_version.clone(),
+                    kind: resource.kind.clone(),
+                },
+                ObjectData {
+                    namespaced: resource.namespaced,
+                    plural: resource.plural.clone(),
+                    is_core: resource.is_core,
+                },
+            );
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_key() {
+        assert_eq!(
+            cache_path(Path::new("/home/user"), "https://10.0.0.1:6443/"),
+            PathBuf::from("/home/user/.kube/cache/hayasaka/https___10.0.0.1_6443.json")
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use super::{apis::canonical_group, find::ObjectKind};
+use std::{
+    convert::Infallible,
+    fmt::{self, Display},
+    str::FromStr,
+};
+
+/// Object kind regardless of version, i.e `discovery.k8s.io/EndpointSlice`,
+/// or just `Endpoints` for core group
+#[derive(Clone, Debug, PartialEq)]
+pub struct GroupKind {
+    pub group: String,
+    pub kind: String,
+}
+
+impl GroupKind {
+    fn new(group: &str, kind: &str) -> Self {
+        Self {
+            group: group.to_owned(),
+            kind: kind.to_owned(),
+        }
+    }
+
+    /// Kinds are compared regardless of group they were accessed through,
+    /// i.e `networking.k8s.io/Ingress` matches `extensions/v1beta1` Ingress
+    pub fn matches(&self, kind: &ObjectKind) -> bool {
+        self.kind == kind.kind
+            && canonical_group(&self.group, &self.kind) == canonical_group(kind.group(), &kind.kind)
+    }
+}
+
+impl FromStr for GroupKind {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.rfind('/') {
+            Some(idx) => Self::new(&s[..idx], &s[idx + 1..]),
+            None => Self::new("", s),
+        })
+    }
+}
+
+impl Display for GroupKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.group.is_empty() {
+            write!(f, "{}/", self.group)?;
+        }
+        write!(f, "{}", self.kind)
+    }
+}
+
+/// Kinds, which are created by controllers and copy labels of our objects,
+/// but have no controller owner reference to tell them apart
+pub fn default_excluded_kinds() -> Vec<GroupKind> {
+    vec![
+        // Endpoints copies Service labels
+        GroupKind::new("", "Endpoints"),
+        GroupKind::new("discovery.k8s.io", "EndpointSlice"),
+    ]
+}
+
+/// Should found object be ignored by prune, destroy and status
+pub fn is_excluded(kind: &ObjectKind, excluded: &[GroupKind]) -> bool {
+    excluded.iter().any(|e| e.matches(kind))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::find::tests::kind;
+
+    #[test]
+    fn matching() {
+        let excluded = default_excluded_kinds();
+        assert!(is_excluded(&kind("v1", "Endpoints"), &excluded));
+        assert!(is_excluded(
+            &kind("discovery.k8s.io/v1", "EndpointSlice"),
+            &excluded
+        ));
+        assert!(is_excluded(
+            &kind("discovery.k8s.io/v1beta1", "EndpointSlice"),
+            &excluded
+        ));
+        assert!(!is_excluded(&kind("v1", "Service"), &excluded));
+
+        let certificates: GroupKind = "cert-manager.io/CertificateRequest".parse().unwrap();
+        assert_eq!(
+            certificates.to_string(),
+            "cert-manager.io/CertificateRequest"
+        );
+        assert!(certificates.matches(&kind("cert-manager.io/v1", "CertificateRequest")));
+
+        let ingresses: GroupKind = "networking.k8s.io/Ingress".parse().unwrap();
+        assert!(ingresses.matches(&kind("networking.k8s.io/v1", "Ingress")));
+        assert!(ingresses.matches(&kind("extensions/v1beta1", "Ingress")));
+        let legacy: GroupKind = "extensions/Ingress".parse().unwrap();
+        assert!(legacy.matches(&kind("networking.k8s.io/v1", "Ingress")));
+        assert!(!ingresses.matches(&kind("networking.k8s.io/v1", "IngressClass")));
+    }
+}
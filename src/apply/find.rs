@@ -3,14 +3,28 @@ use std::{
     fmt::{self, Display},
 };
 
+use futures::{stream, StreamExt};
 use http::Request;
 use kube::Client;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    apis::canonical_group,
+    discovery::{Discovery, Resource},
+};
 
 pub type RuntimeTypeData = BTreeMap<ObjectKind, ObjectData>;
 
+/// Group of apiVersion, empty for core group
+pub fn api_group(api_version: &str) -> &str {
+    match api_version.rfind('/') {
+        Some(idx) => &api_version[..idx],
+        None => "",
+    }
+}
+
 /// Represents object runtime type
-#[derive(Clone, Debug, Deserialize, Eq)]
+#[derive(Clone, Debug, Deserialize, Serialize, Eq)]
 pub struct ObjectKind {
     // extensions/v1
     #[serde(rename = "apiVersion")]
@@ -19,16 +33,13 @@ pub struct ObjectKind {
     pub kind: String,
 }
 impl ObjectKind {
+    /// Group, through which object is accessed, i.e `apps`
+    pub fn group(&self) -> &str {
+        api_group(&self.api_version)
+    }
+
     fn versionless_version(&self) -> &str {
-        let index = self
-            .api_version
-            .find("/")
-            .unwrap_or_else(|| self.api_version.len());
-        let api_version = &self.api_version[0..index];
-        if api_version == "extensions" && self.kind == "Ingress" {
-            return "networking.k8s.io";
-        }
-        api_version
+        canonical_group(self.group(), &self.kind)
     }
 }
 
@@ -59,9 +70,10 @@ impl Display for ObjectKind {
 }
 
 /// Represents object location
-#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
 pub struct ObjectLocation {
     pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub namespace: Option<String>,
 }
 
@@ -76,7 +88,7 @@ impl Display for ObjectLocation {
 }
 
 /// Represents unique object
-#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
 pub struct Object {
     #[serde(flatten)]
     pub kind: ObjectKind,
@@ -89,15 +101,47 @@ impl Display for Object {
     }
 }
 
+/// Represents object owner
+#[derive(Clone, Debug, Deserialize)]
+pub struct OwnerReference {
+    #[serde(default)]
+    pub controller: bool,
+}
+
+/// Represents object list item metadata
+#[derive(Clone, Debug, Deserialize)]
+pub struct ObjectListItemMetadata {
+    #[serde(flatten)]
+    pub location: ObjectLocation,
+    #[serde(rename = "ownerReferences", default)]
+    pub owner_references: Vec<OwnerReference>,
+}
+
+impl ObjectListItemMetadata {
+    /// Object is managed by controller, which may copy our labels to it
+    pub fn is_controlled(&self) -> bool {
+        self.owner_references.iter().any(|r| r.controller)
+    }
+}
+
 /// Represents object list item
-#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct ObjectListItem {
-    pub metadata: ObjectLocation,
+    pub metadata: ObjectListItemMetadata,
+}
+
+/// Represents object list metadata
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ObjectListMetadata {
+    #[serde(rename = "continue", default)]
+    pub continue_token: Option<String>,
 }
 
 /// Represents object list
-#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
-struct ObjectList {
+#[derive(Clone, Debug, Deserialize)]
+pub struct ObjectList {
+    #[serde(default)]
+    pub metadata: ObjectListMetadata,
     pub items: Vec<ObjectListItem>,
 }
 
@@ -108,145 +152,123 @@ pub struct ObjectData {
     pub plural: String,
 }
 
-/// List all defined object kinds with additional meta
-pub async fn list_apis(client: Client) -> super::Result<RuntimeTypeData> {
-    let mut out = BTreeMap::new();
+/// Only metadata is needed, full objects may be huge (i.e Secrets)
+const METADATA_LIST_ACCEPT: &str =
+    "application/json;as=PartialObjectMetadataList;g=meta.k8s.io;v=v1,application/json";
+const LIST_PAGE_SIZE: usize = 500;
+const LIST_CONCURRENCY: usize = 8;
 
-    for version in client.list_core_api_versions().await?.versions {
-        for resource in client.list_core_api_resources(&version).await?.resources {
-            if resource.name.find("/").is_some() {
-                continue;
-            }
-            out.insert(
-                ObjectKind {
-                    api_version: version.clone(),
-                    kind: resource.kind,
-                },
-                ObjectData {
-                    namespaced: resource.namespaced,
-                    plural: resource.name.clone(),
-                    is_core: true,
-                },
-            );
+/// List all labeled items of resource, page by page
+async fn list_labeled(
+    client: Client,
+    resource: &Resource,
+    label_selector: &str,
+) -> Result<Vec<ObjectListItem>, kube::Error> {
+    let mut out = Vec::new();
+    let mut continue_token = None::<String>;
+    loop {
+        let mut url = format!(
+            "{}?labelSelector={}&limit={}",
+            resource.url(),
+            label_selector,
+            LIST_PAGE_SIZE
+        );
+        if let Some(token) = &continue_token {
+            url.push_str("&continue=");
+            url.extend(url::form_urlencoded::byte_serialize(token.as_bytes()));
         }
-    }
-
-    for group in client.list_api_groups().await?.groups {
-        for version in group.versions {
-            for resource in client
-                .list_api_group_resources(&version.group_version)
-                .await?
-                .resources
-            {
-                if resource.name.contains("/") {
-                    continue;
-                }
-                out.insert(
-                    ObjectKind {
-                        api_version: version.group_version.clone(),
-                        kind: resource.kind.clone(),
-                    },
-                    ObjectData {
-                        namespaced: resource.namespaced,
-                        plural: resource.name.clone(),
-                        is_core: false,
-                    },
-                );
-            }
+        let list: ObjectList = client
+            .request(
+                Request::get(&url)
+                    .header("Accept", METADATA_LIST_ACCEPT)
+                    .body(vec![])
+                    .map_err(kube::Error::HttpError)?,
+            )
+            .await?;
+        out.extend(list.items);
+        match list.metadata.continue_token {
+            Some(token) if !token.is_empty() => continue_token = Some(token),
+            _ => return Ok(out),
         }
     }
-
-    Ok(out)
 }
 
 /// Find all objects, which matches given label selector
+///
+/// Objects controlled by other objects are skipped, as they inherit labels from their owners
 pub async fn find_all_labeled_items(
     client: Client,
+    discovery: &Discovery,
     label: (&str, &str),
 ) -> Result<BTreeSet<Object>, anyhow::Error> {
-    let mut out = BTreeSet::new();
     let label_selector = format!("{}={}", label.0, label.1);
 
-    for version in client.list_core_api_versions().await?.versions {
-        let client = client.clone();
-        for resource in client.list_core_api_resources(&version).await?.resources {
-            if resource.name.find("/").is_some() {
-                continue;
-            }
-            let data = client
-                .request::<ObjectList>(
-                    Request::builder()
-                        .uri(&format!(
-                            "/api/{}/{}?labelSelector={}",
-                            version, resource.name, label_selector,
-                        ))
-                        .body(vec![])
-                        .map_err(kube::Error::HttpError)?,
+    let resources = discovery
+        .resources
+        .iter()
+        .filter(|r| r.preferred && r.verbs.iter().any(|v| v == "list"));
+
+    let mut out = BTreeSet::new();
+    let label_selector = &label_selector;
+    let mut lists = stream::iter(resources)
+        .map(|resource| {
+            let client = client.clone();
+            async move {
+                (
+                    resource,
+                    list_labeled(client, resource, label_selector).await,
                 )
-                .await;
-            if let Ok(data) = data {
-                for object in data.items {
-                    out.insert(Object {
-                        kind: ObjectKind {
-                            api_version: version.clone(),
-                            kind: resource.kind.clone(),
-                        },
-                        metadata: object.metadata,
-                    });
-                }
-            } else if !(version == "v1" && resource.name == "bindings") {
-                log::warn!(
-                    "No access, assuming there should be no {} {} deployed",
-                    version,
-                    resource.name
-                );
             }
-        }
-    }
-    for group in client.list_api_groups().await?.groups {
-        let version = group
-            .preferred_version
-            .as_ref()
-            .unwrap_or_else(|| group.versions.last().unwrap());
-        for resource in client
-            .list_api_group_resources(&version.group_version)
-            .await?
-            .resources
-        {
-            if resource.name.contains('/') {
-                continue;
-            }
-            let data = client
-                .request::<ObjectList>(
-                    Request::builder()
-                        .uri(&format!(
-                            "/apis/{}/{}?labelSelector={}",
-                            version.group_version, resource.name, label_selector,
-                        ))
-                        .body(vec![])
-                        .map_err(kube::Error::HttpError)?,
-                )
-                .await;
-            if let Ok(data) = data {
-                for object in data.items {
+        })
+        .buffer_unordered(LIST_CONCURRENCY);
+    while let Some((resource, result)) = lists.next().await {
+        match result {
+            Ok(items) => {
+                for object in items {
+                    if object.metadata.is_controlled() {
+                        continue;
+                    }
                     out.insert(Object {
                         kind: ObjectKind {
-                            api_version: version.group_version.clone(),
+                            api_version: resource.api_version.clone(),
                             kind: resource.kind.clone(),
                         },
-                        metadata: object.metadata,
+                        metadata: object.metadata.location,
                     });
                 }
-            } else if !(group.name == "authentication.k8s.io"
-                || group.name == "authorization.k8s.io")
-            {
-                log::warn!(
-                    "No access, assuming there should be no {} {} deployed",
-                    group.name,
-                    resource.name
-                );
             }
+            Err(e) => log::warn!(
+                "Failed to list {}, assuming there should be no {} {} deployed: {}",
+                resource.url(),
+                resource.api_version,
+                resource.kind,
+                e
+            ),
         }
     }
     Ok(out)
 }
+
+/// Constructors, shared by tests of other modules
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    pub fn kind(api_version: &str, kind: &str) -> ObjectKind {
+        ObjectKind {
+            api_version: api_version.to_owned(),
+            kind: kind.to_owned(),
+        }
+    }
+
+    /// Object in `test` namespace
+    pub fn object(api_version: &str, kind: &str, name: &str) -> Object {
+        Object {
+            kind: self::kind(api_version, kind),
+            metadata: ObjectLocation {
+                name: name.to_owned(),
+                namespace: Some("test".to_owned()),
+            },
+        }
+    }
+}
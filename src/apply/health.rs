@@ -0,0 +1,247 @@
+use super::find::ObjectKind;
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt::{self, Display};
+
+/// Computed object health
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Health {
+    /// Object is fully rolled out
+    Ready,
+    /// Object is being rolled out
+    Progressing,
+    /// Object rollout failed
+    Degraded,
+    /// Object has status, but we don't know how to interpret it
+    Unknown,
+}
+
+impl Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ready => write!(f, "ready"),
+            Self::Progressing => write!(f, "progressing"),
+            Self::Degraded => write!(f, "degraded"),
+            Self::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+fn condition<'v>(value: &'v Value, ty: &str) -> Option<&'v Value> {
+    value["status"]["conditions"]
+        .as_array()?
+        .iter()
+        .find(|c| c["type"] == ty)
+}
+
+fn condition_is(value: &Value, ty: &str, status: &str) -> bool {
+    condition(value, ty)
+        .map(|c| c["status"] == status)
+        .unwrap_or(false)
+}
+
+fn int(value: &Value) -> i64 {
+    value.as_i64().unwrap_or(0)
+}
+
+/// Controller has not yet seen latest object spec
+fn is_outdated(value: &Value) -> bool {
+    match value["status"]["observedGeneration"].as_i64() {
+        Some(observed) => observed < int(&value["metadata"]["generation"]),
+        None => false,
+    }
+}
+
+fn deployment_health(value: &Value) -> Health {
+    let status = &value["status"];
+    if condition(value, "Progressing")
+        .map(|c| c["reason"] == "ProgressDeadlineExceeded")
+        .unwrap_or(false)
+    {
+        return Health::Degraded;
+    }
+    let replicas = value["spec"]["replicas"].as_i64().unwrap_or(1);
+    if is_outdated(value)
+        || int(&status["updatedReplicas"]) < replicas
+        || int(&status["replicas"]) > replicas
+        || int(&status["availableReplicas"]) < replicas
+    {
+        return Health::Progressing;
+    }
+    Health::Ready
+}
+
+fn stateful_set_health(value: &Value) -> Health {
+    let status = &value["status"];
+    let replicas = value["spec"]["replicas"].as_i64().unwrap_or(1);
+    if is_outdated(value)
+        || int(&status["readyReplicas"]) < replicas
+        || status["currentRevision"] != status["updateRevision"]
+    {
+        return Health::Progressing;
+    }
+    Health::Ready
+}
+
+fn daemon_set_health(value: &Value) -> Health {
+    let status = &value["status"];
+    let desired = int(&status["desiredNumberScheduled"]);
+    if is_outdated(value)
+        || int(&status["updatedNumberScheduled"]) < desired
+        || int(&status["numberAvailable"]) < desired
+    {
+        return Health::Progressing;
+    }
+    Health::Ready
+}
+
+fn job_health(value: &Value) -> Health {
+    if condition_is(value, "Failed", "True") {
+        Health::Degraded
+    } else if condition_is(value, "Complete", "True") {
+        Health::Ready
+    } else {
+        Health::Progressing
+    }
+}
+
+fn pod_health(value: &Value) -> Health {
+    match value["status"]["phase"].as_str() {
+        Some("Succeeded") => Health::Ready,
+        Some("Running") if condition_is(value, "Ready", "True") => Health::Ready,
+        Some("Running") | Some("Pending") => Health::Progressing,
+        Some("Failed") => Health::Degraded,
+        _ => Health::Unknown,
+    }
+}
+
+fn pvc_health(value: &Value) -> Health {
+    match value["status"]["phase"].as_str() {
+        Some("Bound") => Health::Ready,
+        Some("Pending") => Health::Progressing,
+        Some("Lost") => Health::Degraded,
+        _ => Health::Unknown,
+    }
+}
+
+fn service_health(value: &Value) -> Health {
+    if value["spec"]["type"] != "LoadBalancer" {
+        return Health::Ready;
+    }
+    match value["status"]["loadBalancer"]["ingress"].as_array() {
+        Some(ingress) if !ingress.is_empty() => Health::Ready,
+        _ => Health::Progressing,
+    }
+}
+
+/// Generic health, based on status conditions
+fn generic_health(value: &Value) -> Health {
+    match value.get("status") {
+        None | Some(Value::Null) => return Health::Ready,
+        Some(Value::Object(status)) if status.is_empty() => return Health::Ready,
+        _ => {}
+    }
+    match condition(value, "Ready").and_then(|c| c["status"].as_str()) {
+        Some("True") => Health::Ready,
+        Some("False") => Health::Degraded,
+        Some(_) => Health::Progressing,
+        None => Health::Unknown,
+    }
+}
+
+/// Compute health of live object
+pub fn health(kind: &ObjectKind, value: &Value) -> Health {
+    match (kind.group(), kind.kind.as_str()) {
+        ("apps", "Deployment") | ("extensions", "Deployment") => deployment_health(value),
+        ("apps", "StatefulSet") => stateful_set_health(value),
+        ("apps", "DaemonSet") | ("extensions", "DaemonSet") => daemon_set_health(value),
+        ("batch", "Job") => job_health(value),
+        ("", "Pod") => pod_health(value),
+        ("", "PersistentVolumeClaim") => pvc_health(value),
+        ("", "Service") => service_health(value),
+        _ => generic_health(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::find::tests::kind;
+    use serde_json::json;
+
+    #[test]
+    fn deployment() {
+        let deployment = kind("apps/v1", "Deployment");
+        assert_eq!(
+            health(
+                &deployment,
+                &json!({
+                    "metadata": {"generation": 2},
+                    "spec": {"replicas": 2},
+                    "status": {
+                        "observedGeneration": 2,
+                        "replicas": 2,
+                        "updatedReplicas": 2,
+                        "availableReplicas": 2,
+                    },
+                })
+            ),
+            Health::Ready
+        );
+        assert_eq!(
+            health(
+                &deployment,
+                &json!({
+                    "metadata": {"generation": 3},
+                    "spec": {"replicas": 2},
+                    "status": {
+                        "observedGeneration": 2,
+                        "replicas": 2,
+                        "updatedReplicas": 2,
+                        "availableReplicas": 2,
+                    },
+                })
+            ),
+            Health::Progressing
+        );
+        assert_eq!(
+            health(
+                &deployment,
+                &json!({
+                    "spec": {"replicas": 2},
+                    "status": {
+                        "conditions": [{
+                            "type": "Progressing",
+                            "status": "False",
+                            "reason": "ProgressDeadlineExceeded",
+                        }],
+                    },
+                })
+            ),
+            Health::Degraded
+        );
+    }
+
+    #[test]
+    fn generic() {
+        assert_eq!(
+            health(&kind("v1", "ConfigMap"), &json!({"data": {}})),
+            Health::Ready
+        );
+        assert_eq!(
+            health(
+                &kind("cert-manager.io/v1", "Certificate"),
+                &json!({"status": {"conditions": [{"type": "Ready", "status": "False"}]}})
+            ),
+            Health::Degraded
+        );
+        assert_eq!(
+            health(
+                &kind("example.com/v1", "Thing"),
+                &json!({"status": {"phase": "Whatever"}})
+            ),
+            Health::Unknown
+        );
+    }
+}
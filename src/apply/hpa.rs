@@ -0,0 +1,147 @@
+use super::find::{Object, ObjectKind, ObjectLocation};
+use serde_json::Value;
+use std::collections::BTreeSet;
+
+/// Objects, which are scaled by HorizontalPodAutoscaler from the same deployment
+pub fn autoscaled_objects(target: &[(Object, Value)]) -> BTreeSet<Object> {
+    let mut out = BTreeSet::new();
+    for (object, value) in target {
+        if object.kind.kind != "HorizontalPodAutoscaler"
+            || !object.kind.api_version.starts_with("autoscaling/")
+        {
+            continue;
+        }
+        let target_ref = &value["spec"]["scaleTargetRef"];
+        if let (Some(api_version), Some(kind), Some(name)) = (
+            target_ref["apiVersion"].as_str(),
+            target_ref["kind"].as_str(),
+            target_ref["name"].as_str(),
+        ) {
+            out.insert(Object {
+                kind: ObjectKind {
+                    api_version: api_version.to_owned(),
+                    kind: kind.to_owned(),
+                },
+                metadata: ObjectLocation {
+                    name: name.to_owned(),
+                    namespace: object.metadata.namespace.clone(),
+                },
+            });
+        }
+    }
+    out
+}
+
+/// Does manager own `.spec.replicas` of live object
+fn owns_replicas(live: &Value, manager: &str) -> bool {
+    live["metadata"]["managedFields"]
+        .as_array()
+        .map(|entries| {
+            entries.iter().any(|entry| {
+                entry["manager"] == manager
+                    && entry["operation"] == "Apply"
+                    && entry["fieldsV1"]["f:spec"]["f:replicas"].is_object()
+            })
+        })
+        .unwrap_or(false)
+}
+
+/// Prevent fight with autoscaler over `.spec.replicas`
+///
+/// Replicas are only set on creation, for existing objects field is removed from
+/// applied configuration. If field is still owned by us, it's removal will reset it
+/// to default value, so instead live value is applied, and ownership will go to autoscaler
+/// on next scale
+pub fn adjust_replicas(object: &Object, item: &mut Value, live: Option<&Value>, manager: &str) {
+    let spec = match item["spec"].as_object_mut() {
+        Some(spec) if spec.contains_key("replicas") => spec,
+        _ => return,
+    };
+    let live = match live {
+        Some(live) => live,
+        None => {
+            log::info!(
+                "{} is autoscaled, .spec.replicas is only set on creation",
+                object
+            );
+            return;
+        }
+    };
+    match &live["spec"]["replicas"] {
+        replicas if !replicas.is_null() && owns_replicas(live, manager) => {
+            log::info!(
+                "{} is autoscaled, using live .spec.replicas = {}",
+                object,
+                replicas
+            );
+            spec.insert("replicas".to_owned(), replicas.clone());
+        }
+        _ => {
+            log::info!("{} is autoscaled, not applying .spec.replicas", object);
+            spec.remove("replicas");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::find::tests::object;
+    use serde_json::json;
+
+    #[test]
+    fn targets() {
+        let target = vec![
+            (
+                object("apps/v1", "Deployment", "web"),
+                json!({"spec": {"replicas": 3}}),
+            ),
+            (
+                object("autoscaling/v2beta2", "HorizontalPodAutoscaler", "web"),
+                json!({"spec": {"scaleTargetRef": {
+                    "apiVersion": "apps/v1",
+                    "kind": "Deployment",
+                    "name": "web",
+                }}}),
+            ),
+        ];
+        let scaled = autoscaled_objects(&target);
+        assert_eq!(scaled.len(), 1);
+        assert!(scaled.contains(&object("apps/v1", "Deployment", "web")));
+    }
+
+    #[test]
+    fn adjust() {
+        let web = object("apps/v1", "Deployment", "web");
+        let manager = "hayasaka.delta.rocks/test";
+
+        let mut item = json!({"spec": {"replicas": 3}});
+        adjust_replicas(&web, &mut item, None, manager);
+        assert_eq!(item, json!({"spec": {"replicas": 3}}));
+
+        let mut item = json!({"spec": {"replicas": 3}});
+        adjust_replicas(
+            &web,
+            &mut item,
+            Some(&json!({"spec": {"replicas": 5}})),
+            manager,
+        );
+        assert_eq!(item, json!({"spec": {}}));
+
+        let mut item = json!({"spec": {"replicas": 3}});
+        adjust_replicas(
+            &web,
+            &mut item,
+            Some(&json!({
+                "metadata": {"managedFields": [{
+                    "manager": manager,
+                    "operation": "Apply",
+                    "fieldsV1": {"f:spec": {"f:replicas": {}}},
+                }]},
+                "spec": {"replicas": 5},
+            })),
+            manager,
+        );
+        assert_eq!(item, json!({"spec": {"replicas": 5}}));
+    }
+}
@@ -0,0 +1,231 @@
+use super::{find::Object, redact::redacted, Error, Result};
+use fieldpath::{Element, FieldpathExt, PathBuf};
+use futures::StreamExt;
+use kube::{error::ErrorResponse, Client};
+use serde_json::Value;
+use std::fmt::{self, Display};
+
+/// Single reason of object rejection
+#[derive(Debug)]
+pub struct Cause {
+    /// Field as reported by apiserver, i.e `spec.containers[0].image`
+    pub field: String,
+    pub path: Option<PathBuf>,
+    /// Message of apiserver, with sensitive value replaced
+    pub message: String,
+    /// Value of field in rendered object, redacted if sensitive
+    pub value: Option<Value>,
+}
+
+/// Object, rejected by apiserver validation
+#[derive(Debug)]
+pub struct InvalidObject {
+    pub object: Object,
+    pub causes: Vec<Cause>,
+}
+
+impl Display for InvalidObject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is invalid:", self.object)?;
+        for cause in &self.causes {
+            write!(f, "\n  ")?;
+            if let Some(path) = &cause.path {
+                write!(f, "{}: ", path)?;
+            } else if !cause.field.is_empty() {
+                write!(f, "{}: ", cause.field)?;
+            }
+            write!(f, "{}", cause.message)?;
+            if let Some(value) = &cause.value {
+                write!(f, " (rendered value: {})", value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parse apiserver field path, i.e `metadata.labels[app.kubernetes.io/name]` or `spec.ports[0].port`
+fn parse_field(field: &str) -> Option<PathBuf> {
+    let mut out = Vec::new();
+    let mut rest = field;
+    while !rest.is_empty() {
+        if let Some(stripped) = rest.strip_prefix('[') {
+            let end = stripped.find(']')?;
+            let key = &stripped[..end];
+            out.push(match key.parse() {
+                Ok(idx) => Element::Index(idx),
+                Err(_) => Element::Field(key.to_owned()),
+            });
+            rest = &stripped[end + 1..];
+        } else {
+            let name = rest.strip_prefix('.').unwrap_or(rest);
+            let end = name.find(&['.', '['][..]).unwrap_or(name.len());
+            if end == 0 {
+                return None;
+            }
+            out.push(Element::Field(name[..end].to_owned()));
+            rest = &name[end..];
+        }
+    }
+    if out.is_empty() {
+        return None;
+    }
+    Some(PathBuf(out))
+}
+
+/// Replace sensitive value in message, i.e `Invalid value: "hunter2": ...`
+///
+/// If value isn't found as is, only the reason before it is kept
+fn redact_message(message: &str, raw: &Value, redacted: &Value) -> String {
+    if raw == redacted {
+        return message.to_owned();
+    }
+    let mut forms = vec![raw.to_string()];
+    if let Some(raw) = raw.as_str() {
+        forms.push(raw.to_owned());
+    }
+    let mut out = message.to_owned();
+    for form in forms.iter().filter(|f| !f.is_empty()) {
+        out = out.replace(form.as_str(), &redacted.to_string());
+    }
+    if out == message {
+        return message.split(':').next().unwrap_or_default().to_owned();
+    }
+    out
+}
+
+fn parse_causes(target: &Value, causes: &[Value]) -> Vec<Cause> {
+    let redacted_target = redacted(target);
+    causes
+        .iter()
+        .map(|cause| {
+            let field = cause["field"].as_str().unwrap_or_default().to_owned();
+            let path = parse_field(&field);
+            let mut message = cause["message"].as_str().unwrap_or_default().to_owned();
+            let value = path.as_ref().and_then(|path| {
+                let raw = target.get_path(path).ok()?;
+                let value = redacted_target.get_path(path).ok()?;
+                message = redact_message(&message, raw, value);
+                Some(value.clone())
+            });
+            Cause {
+                field,
+                path,
+                message,
+                value,
+            }
+        })
+        .collect()
+}
+
+/// Perform apply request, turning validation failure into [`InvalidObject`] error
+///
+/// kube error only keeps status message, so response is parsed manually
+pub async fn request_apply(
+    client: Client,
+    req: http::Request<Vec<u8>>,
+    object: &Object,
+    target: &Value,
+) -> Result<Value> {
+    let mut stream = client.request_text_stream(req).await?;
+    let mut body = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        body.extend_from_slice(&chunk?);
+    }
+    let value: Value = match serde_json::from_slice(&body) {
+        Ok(v) => v,
+        Err(_) => {
+            return Err(kube::Error::Api(ErrorResponse {
+                status: "Failure".to_owned(),
+                message: String::from_utf8_lossy(&body).into_owned(),
+                reason: String::new(),
+                code: 0,
+            })
+            .into())
+        }
+    };
+    if value["kind"] != "Status" || value["status"] != "Failure" {
+        return Ok(value);
+    }
+
+    let code = value["code"].as_u64().unwrap_or(0) as u16;
+    match value["details"]["causes"].as_array() {
+        Some(causes) if code == 422 && !causes.is_empty() => Err(Error::Invalid(InvalidObject {
+            object: object.clone(),
+            causes: parse_causes(target, causes),
+        })),
+        _ => Err(kube::Error::Api(ErrorResponse {
+            status: "Failure".to_owned(),
+            message: value["message"].as_str().unwrap_or_default().to_owned(),
+            reason: value["reason"].as_str().unwrap_or_default().to_owned(),
+            code,
+        })
+        .into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn fields() {
+        assert_eq!(
+            parse_field("spec.containers[0].image").unwrap().to_string(),
+            ".spec.containers[0].image"
+        );
+        assert_eq!(
+            parse_field("metadata.labels[app.kubernetes.io/name]")
+                .unwrap()
+                .to_string(),
+            r#".metadata.labels."app.kubernetes.io/name""#
+        );
+        assert!(parse_field("").is_none());
+    }
+
+    #[test]
+    fn causes() {
+        let target = json!({"spec": {"ports": [{"port": 100000}]}});
+        let causes = parse_causes(
+            &target,
+            &[json!({
+                "reason": "FieldValueInvalid",
+                "message": "Invalid value: 100000: must be between 1 and 65535, inclusive",
+                "field": "spec.ports[0].port",
+            })],
+        );
+        assert_eq!(causes[0].value, Some(json!(100000)));
+        assert_eq!(
+            causes[0].message,
+            "Invalid value: 100000: must be between 1 and 65535, inclusive"
+        );
+    }
+
+    #[test]
+    fn secret_causes() {
+        let target = json!({
+            "apiVersion": "v1",
+            "kind": "Secret",
+            "data": {"password": "hunter2", "token": {"nested": "s3cret"}},
+        });
+        let causes = parse_causes(
+            &target,
+            &[
+                json!({
+                    "message": "Invalid value: \"hunter2\": a valid secret key must be base64",
+                    "field": "data[password]",
+                }),
+                json!({
+                    "message": "Invalid value: map[nested:s3cret]: must be a string",
+                    "field": "data[token]",
+                }),
+            ],
+        );
+        let message = &causes[0].message;
+        assert!(!message.contains("hunter2"));
+        assert!(message.starts_with("Invalid value: \"<redacted "));
+        assert!(message.ends_with(": a valid secret key must be base64"));
+        assert_eq!(causes[1].message, "Invalid value");
+        assert!(!format!("{:?}", causes).contains("s3cret"));
+    }
+}
@@ -0,0 +1,278 @@
+use super::{get, Error, Result};
+use chrono::{DateTime, Duration as ChronoDuration, SecondsFormat, Utc};
+use kube::Client;
+use serde_json::{json, Value};
+use std::time::{Duration, Instant};
+use tokio::{sync::watch, task::JoinHandle, time::sleep};
+
+/// For how long lock is valid without renewal
+const LEASE_DURATION: Duration = Duration::from_secs(60);
+
+fn leases_url(namespace: &str) -> String {
+    format!(
+        "/apis/coordination.k8s.io/v1/namespaces/{}/leases",
+        namespace
+    )
+}
+
+/// Name of deployment lock Lease
+pub fn lock_name(label: (&str, &str)) -> String {
+    format!("hayasaka-lock-{}", label.1)
+}
+
+fn now() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+/// Identity of current process, used as lock holder
+pub fn default_holder() -> String {
+    let user = std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_owned());
+    let host = std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/proc/sys/kernel/hostname").ok())
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|h| h.trim().to_owned())
+        .unwrap_or_else(|| "unknown".to_owned());
+    format!("{}@{} ({})", user, host, std::process::id())
+}
+
+fn is_expired(lease: &Value) -> bool {
+    let spec = &lease["spec"];
+    let renewed = match spec["renewTime"]
+        .as_str()
+        .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+    {
+        Some(t) => t.with_timezone(&Utc),
+        None => return true,
+    };
+    let duration = spec["leaseDurationSeconds"].as_i64().unwrap_or(0);
+    renewed + ChronoDuration::seconds(duration) < Utc::now()
+}
+
+async fn send(client: Client, req: http::Request<Vec<u8>>) -> Result<bool> {
+    match client.request::<Value>(req).await {
+        Ok(_) => Ok(true),
+        // Someone else created/updated lease at the same time
+        Err(kube::Error::Api(apierror)) if apierror.code == 409 => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Create or take over lease, returns false on concurrent modification
+async fn try_take(
+    client: Client,
+    namespace: &str,
+    name: &str,
+    holder: &str,
+    existing: Option<Value>,
+) -> Result<bool> {
+    let time = now();
+    let spec = json!({
+        "holderIdentity": holder,
+        "leaseDurationSeconds": LEASE_DURATION.as_secs(),
+        "acquireTime": time,
+        "renewTime": time,
+    });
+    let req = match existing {
+        None => http::Request::post(&leases_url(namespace))
+            .header("Accept", "application/json")
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_vec(&json!({
+                "apiVersion": "coordination.k8s.io/v1",
+                "kind": "Lease",
+                "metadata": {
+                    "name": name,
+                    "namespace": namespace,
+                },
+                "spec": spec,
+            }))?),
+        Some(mut lease) => {
+            let transitions = lease["spec"]["leaseTransitions"].as_i64().unwrap_or(0);
+            lease["spec"] = spec;
+            lease["spec"]["leaseTransitions"] = json!(transitions + 1);
+            http::Request::put(&format!("{}/{}", leases_url(namespace), name))
+                .header("Accept", "application/json")
+                .header("Content-Type", "application/json")
+                .body(serde_json::to_vec(&lease)?)
+        }
+    }
+    .map_err(kube::Error::HttpError)?;
+
+    send(client, req).await
+}
+
+/// Get lease, failing if it is not held by holder anymore
+async fn get_held(client: Client, url: &str, holder: &str) -> Result<Value> {
+    let lease = match get(client, url).await? {
+        Some(v) => v,
+        None => return Err(Error::LockLost("lease was removed".to_owned())),
+    };
+    if lease["spec"]["holderIdentity"] != holder {
+        return Err(Error::LockLost(format!(
+            "lease was taken over by {}",
+            lease["spec"]["holderIdentity"]
+        )));
+    }
+    Ok(lease)
+}
+
+/// Update renew time of held lease, returns false on concurrent modification
+async fn renew(client: Client, namespace: &str, name: &str, holder: &str) -> Result<bool> {
+    let url = format!("{}/{}", leases_url(namespace), name);
+    let mut lease = get_held(client.clone(), &url, holder).await?;
+    lease["spec"]["renewTime"] = json!(now());
+
+    let req = http::Request::put(&url)
+        .header("Accept", "application/json")
+        .header("Content-Type", "application/json")
+        .body(serde_json::to_vec(&lease)?)
+        .map_err(kube::Error::HttpError)?;
+    send(client, req).await
+}
+
+/// Loss of deployment lock, as seen by renewal task
+#[derive(Clone)]
+pub struct LockState(watch::Receiver<Option<String>>);
+
+impl LockState {
+    /// Fail if lock is lost, deployment should be stopped, as other run may be in progress
+    pub fn check(&self) -> Result<()> {
+        match &*self.0.borrow() {
+            Some(reason) => Err(Error::LockLost(reason.clone())),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Lease-based lock, prevents concurrent deployments with the same name
+pub struct DeploymentLock {
+    client: Client,
+    url: String,
+    holder: String,
+    renewal: JoinHandle<()>,
+    state: LockState,
+}
+
+impl DeploymentLock {
+    /// Acquire lease named after deployment
+    ///
+    /// If lock is held by someone else, waits up to `wait`, unless `force` is set,
+    /// in which case lock is taken over
+    pub async fn acquire(
+        client: Client,
+        namespace: &str,
+        label: (&str, &str),
+        holder: &str,
+        wait: Duration,
+        force: bool,
+    ) -> Result<Self> {
+        let name = lock_name(label);
+        let url = format!("{}/{}", leases_url(namespace), name);
+        let deadline = Instant::now() + wait;
+
+        let mut logged = false;
+        loop {
+            let existing = get(client.clone(), &url).await?;
+            let current_holder = existing
+                .as_ref()
+                .and_then(|lease| lease["spec"]["holderIdentity"].as_str())
+                .map(ToOwned::to_owned);
+            let free = match (&existing, &current_holder) {
+                (None, _) | (_, None) => true,
+                (Some(lease), Some(current)) => current == holder || is_expired(lease),
+            };
+            if !free && force {
+                log::warn!(
+                    "forcibly taking over lock held by {}",
+                    current_holder.as_deref().unwrap_or_default()
+                );
+            }
+
+            if free || force {
+                if try_take(client.clone(), namespace, &name, holder, existing).await? {
+                    break;
+                }
+                continue;
+            }
+
+            let current_holder = current_holder.unwrap_or_default();
+            if Instant::now() >= deadline {
+                return Err(Error::Locked(current_holder));
+            }
+            if !logged {
+                log::info!("waiting for lock held by {}", current_holder);
+                logged = true;
+            }
+            sleep(Duration::from_secs(2)).await;
+        }
+        log::info!("acquired lock as {}", holder);
+
+        let (lost, state) = watch::channel(None);
+        let renewal = {
+            let client = client.clone();
+            let namespace = namespace.to_owned();
+            let holder = holder.to_owned();
+            tokio::spawn(async move {
+                let mut renewed = Instant::now();
+                loop {
+                    sleep(LEASE_DURATION / 3).await;
+                    match renew(client.clone(), &namespace, &name, &holder).await {
+                        Ok(true) => renewed = Instant::now(),
+                        Ok(false) => {}
+                        Err(Error::LockLost(reason)) => {
+                            let _ = lost.send(Some(reason));
+                            break;
+                        }
+                        Err(e) => log::warn!("failed to renew lock: {}", e),
+                    }
+                    // Lease may be taken by another run after expiration
+                    if renewed.elapsed() >= LEASE_DURATION {
+                        let _ = lost.send(Some("lease has expired".to_owned()));
+                        break;
+                    }
+                }
+            })
+        };
+
+        Ok(Self {
+            client,
+            url,
+            holder: holder.to_owned(),
+            renewal,
+            state: LockState(state),
+        })
+    }
+
+    pub fn state(&self) -> LockState {
+        self.state.clone()
+    }
+
+    /// Stop renewal, and remove lease, if it is still held
+    pub async fn release(self) -> Result<()> {
+        self.renewal.abort();
+
+        let lease = get_held(self.client.clone(), &self.url, &self.holder).await?;
+        // Lease may be taken over between get and delete
+        let req = http::Request::delete(&self.url)
+            .header("Accept", "application/json")
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_vec(&json!({
+                "apiVersion": "v1",
+                "kind": "DeleteOptions",
+                "preconditions": {
+                    "resourceVersion": lease["metadata"]["resourceVersion"],
+                },
+            }))?)
+            .map_err(kube::Error::HttpError)?;
+        match self.client.request::<Value>(req).await {
+            Ok(_) => Ok(()),
+            Err(kube::Error::Api(apierror)) if apierror.code == 404 => Ok(()),
+            Err(kube::Error::Api(apierror)) if apierror.code == 409 => Err(Error::LockLost(
+                "lease was taken over during release".to_owned(),
+            )),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
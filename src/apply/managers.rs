@@ -0,0 +1,298 @@
+use super::{default_excluded_kinds, discovery::Discovery, find_deployed, get, make_url, Result};
+use chrono::{SecondsFormat, Utc};
+use kube::Client;
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+
+/// Union of two FieldsV1 sets
+fn merge_fields(into: &mut Value, from: &Value) {
+    match (into, from) {
+        (Value::Object(into), Value::Object(from)) => {
+            for (key, value) in from {
+                match into.get_mut(key) {
+                    Some(existing) => merge_fields(existing, value),
+                    None => {
+                        into.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        (into, from) => *into = from.clone(),
+    }
+}
+
+/// Paths of fields in FieldsV1 set, i.e `.spec.replicas`
+fn field_paths(fields: &Value, prefix: &str, out: &mut BTreeSet<String>) {
+    let fields = match fields.as_object() {
+        Some(v) if !v.is_empty() => v,
+        _ => {
+            out.insert(prefix.to_owned());
+            return;
+        }
+    };
+    for (key, value) in fields {
+        let path = match key.split_at(key.find(':').map(|i| i + 1).unwrap_or(0)) {
+            ("", ".") => {
+                out.insert(prefix.to_owned());
+                continue;
+            }
+            ("f:", name) => format!("{}.{}", prefix, name),
+            (_, item) => format!("{}[{}]", prefix, item),
+        };
+        field_paths(value, &path, out);
+    }
+}
+
+struct Migration {
+    /// New managedFields
+    entries: Vec<Value>,
+    /// Fields, which will be owned only by new manager without being declared by it,
+    /// and will be removed by next apply
+    dropped: Vec<String>,
+}
+
+/// Merge entries of `from` managers (with any operation) into single `to` Apply entry
+///
+/// Entries of `from` managers are removed, so they wouldn't stay as owners of fields,
+/// which are no longer declared by `to`, and these fields will be removed by next apply.
+/// Only entries of `api_version` (or version of existing `to` entry) are merged,
+/// as field sets of different versions aren't compatible
+///
+/// Returns None, if there is nothing to migrate
+fn migrate_entries(
+    entries: &[Value],
+    api_version: &str,
+    from: &[String],
+    to: &str,
+    time: &str,
+) -> Option<Migration> {
+    let is_old = |entry: &Value| {
+        entry["manager"]
+            .as_str()
+            .map(|manager| from.iter().any(|f| f == manager))
+            .unwrap_or(false)
+    };
+    let is_current = |entry: &Value| entry["manager"] == to && entry["operation"] == "Apply";
+    let api_version = entries
+        .iter()
+        .find(|e| is_current(e))
+        .map(|e| e["apiVersion"].clone())
+        .unwrap_or_else(|| json!(api_version));
+    // Subresource ownership (i.e status) shouldn't be transferred to main resource manager
+    let is_subresource = |entry: &Value| {
+        entry["subresource"]
+            .as_str()
+            .map(|s| !s.is_empty())
+            .unwrap_or(false)
+    };
+    let is_merged = |entry: &Value| {
+        !is_subresource(entry) && entry["apiVersion"] == api_version && is_old(entry)
+    };
+
+    if !entries.iter().any(&is_merged) {
+        return None;
+    }
+
+    let mut out = Vec::new();
+    let mut fields = json!({});
+    let mut old_fields = BTreeSet::new();
+    let mut kept_fields = BTreeSet::new();
+    for entry in entries {
+        if is_merged(entry) {
+            field_paths(&entry["fieldsV1"], "", &mut old_fields);
+        } else if is_current(entry) {
+            field_paths(&entry["fieldsV1"], "", &mut kept_fields);
+        } else {
+            if !is_subresource(entry) {
+                field_paths(&entry["fieldsV1"], "", &mut kept_fields);
+            }
+            out.push(entry.clone());
+            continue;
+        }
+        merge_fields(&mut fields, &entry["fieldsV1"]);
+    }
+    out.push(json!({
+        "manager": to,
+        "operation": "Apply",
+        "apiVersion": api_version,
+        "time": time,
+        "fieldsType": "FieldsV1",
+        "fieldsV1": fields,
+    }));
+
+    Some(Migration {
+        entries: out,
+        dropped: old_fields.difference(&kept_fields).cloned().collect(),
+    })
+}
+
+/// Transfer field ownership from old managers to specified one on every deployed object
+///
+/// Returns number of migrated objects
+pub async fn migrate_managers(
+    client: Client,
+    discovery: &Discovery,
+    label: (&str, &str),
+    from: &[String],
+    to: &str,
+    dry_run: bool,
+) -> Result<usize> {
+    let types = discovery.types();
+    let time = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+
+    let mut migrated = 0;
+    for object in find_deployed(client.clone(), discovery, label, &default_excluded_kinds()).await?
+    {
+        let url = make_url("", &object, &types);
+        let value = match get(client.clone(), &url).await? {
+            Some(v) => v,
+            None => continue,
+        };
+        let entries = match value["metadata"]["managedFields"].as_array() {
+            Some(v) => v,
+            None => continue,
+        };
+        let api_version = value["apiVersion"].as_str().unwrap_or_default();
+        let migration = match migrate_entries(entries, api_version, from, to, &time) {
+            Some(v) => v,
+            None => continue,
+        };
+
+        log::warn!("migrating managers of {}", object);
+        if !migration.dropped.is_empty() {
+            log::warn!(
+                "fields of {}, which are not declared by deployment, will be removed by next deploy:\n  {}",
+                object,
+                migration.dropped.join("\n  ")
+            );
+        }
+        migrated += 1;
+        if dry_run {
+            continue;
+        }
+
+        let patch = json!([
+            {
+                "op": "test",
+                "path": "/metadata/resourceVersion",
+                "value": value["metadata"]["resourceVersion"],
+            },
+            {
+                "op": "replace",
+                "path": "/metadata/managedFields",
+                "value": migration.entries,
+            },
+        ]);
+        let req = http::Request::patch(&url)
+            .header("Accept", "application/json")
+            .header("Content-Type", "application/json-patch+json")
+            .body(serde_json::to_vec(&patch)?)
+            .map_err(kube::Error::HttpError)?;
+        let _result: Value = client.request(req).await?;
+    }
+
+    Ok(migrated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge() {
+        let entries = vec![
+            json!({
+                "manager": "kubectl-client-side-apply",
+                "operation": "Update",
+                "apiVersion": "apps/v1",
+                "fieldsType": "FieldsV1",
+                "fieldsV1": {"f:spec": {"f:replicas": {}, "f:paused": {}}},
+            }),
+            json!({
+                "manager": "kube-controller-manager",
+                "operation": "Update",
+                "apiVersion": "apps/v1",
+                "fieldsType": "FieldsV1",
+                "fieldsV1": {"f:status": {"f:replicas": {}}},
+            }),
+            json!({
+                "manager": "hayasaka.delta.rocks/test",
+                "operation": "Apply",
+                "apiVersion": "apps/v1",
+                "fieldsType": "FieldsV1",
+                "fieldsV1": {"f:spec": {"f:template": {}}},
+            }),
+        ];
+
+        let migrated = migrate_entries(
+            &entries,
+            "apps/v1",
+            &["kubectl-client-side-apply".to_owned()],
+            "hayasaka.delta.rocks/test",
+            "2021-03-28T00:00:00Z",
+        )
+        .unwrap();
+        assert_eq!(migrated.dropped, vec![".spec.paused", ".spec.replicas"]);
+        assert_eq!(
+            migrated.entries,
+            vec![
+                entries[1].clone(),
+                json!({
+                    "manager": "hayasaka.delta.rocks/test",
+                    "operation": "Apply",
+                    "apiVersion": "apps/v1",
+                    "time": "2021-03-28T00:00:00Z",
+                    "fieldsType": "FieldsV1",
+                    "fieldsV1": {"f:spec": {"f:replicas": {}, "f:paused": {}, "f:template": {}}},
+                }),
+            ]
+        );
+
+        assert!(migrate_entries(
+            &entries,
+            "apps/v1",
+            &["helm".to_owned()],
+            "hayasaka.delta.rocks/test",
+            "2021-03-28T00:00:00Z",
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn other_version() {
+        let entries = vec![json!({
+            "manager": "kubectl-client-side-apply",
+            "operation": "Update",
+            "apiVersion": "extensions/v1beta1",
+            "fieldsType": "FieldsV1",
+            "fieldsV1": {"f:spec": {"f:replicas": {}}},
+        })];
+        assert!(migrate_entries(
+            &entries,
+            "apps/v1",
+            &["kubectl-client-side-apply".to_owned()],
+            "hayasaka.delta.rocks/test",
+            "2021-03-28T00:00:00Z",
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn paths() {
+        let mut out = BTreeSet::new();
+        field_paths(
+            &json!({"f:metadata": {"f:labels": {".": {}, "f:app": {}}}, "f:spec": {"f:ports": {"k:{\"port\":80}": {".": {}, "f:name": {}}}}}),
+            "",
+            &mut out,
+        );
+        assert_eq!(
+            out.into_iter().collect::<Vec<_>>(),
+            vec![
+                ".metadata.labels",
+                ".metadata.labels.app",
+                ".spec.ports[{\"port\":80}]",
+                ".spec.ports[{\"port\":80}].name",
+            ]
+        );
+    }
+}
@@ -1,11 +1,53 @@
+mod access;
+mod apis;
+mod checksum;
+mod destroy;
+mod diff;
+mod discovery;
+mod events;
+mod exclude;
 mod find;
+mod health;
+mod hpa;
+mod invalid;
+mod lock;
+mod managers;
+mod namespaces;
+mod order;
 mod parse;
-
-use fieldpath::{path, Element, FieldpathExt, Path, PathBuf};
+mod phase;
+mod rbac;
+mod redact;
+mod report;
+mod revision;
+mod select;
+mod status;
+mod unchanged;
+
+pub use destroy::{destroy_multi, find_deployed};
+pub use diff::format_diff;
+pub use discovery::{cache_path as discovery_cache_path, Discovery};
+pub use events::{Event, EventKind, Sink, Sinks};
+pub use exclude::{default_excluded_kinds, GroupKind};
+pub use lock::{default_holder, DeploymentLock, LockState};
+pub use managers::migrate_managers;
+pub use namespaces::{create_namespace, find_created_namespaces};
+pub use phase::Phase;
+pub use rbac::generate_rbac;
+pub use redact::{redacted, set_redacted_paths};
+pub use report::{Outcome, Report};
+pub use revision::remove_revision;
+pub use select::{Selection, Selector};
+pub use status::{status, DeploymentStatus};
+use crate::progress;
+use fieldpath::{Element, FieldpathExt, Path, PathBuf};
 use find::{Object, ObjectKind, RuntimeTypeData};
 use kube::{api::DeleteParams, Client};
 use serde_json::{json, Value};
-use std::collections::BTreeSet;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::{Duration, Instant},
+};
 use thiserror::Error;
 
 /// How to deal with conflict
@@ -28,6 +70,26 @@ pub enum Error {
     UnknownObjectKind(ObjectKind),
     #[error("conflict resolution failed: {0}")]
     ConflictResolverError(String),
+    #[error("timed out waiting for {0} to be removed")]
+    RemoveTimeout(Object),
+    #[error("deployment is locked by {0}, use --force-unlock if this lock is stale")]
+    Locked(String),
+    #[error("deployment lock is lost: {0}")]
+    LockLost(String),
+    #[error("unknown phase of {0}: {1}")]
+    UnknownPhase(Object, String),
+    #[error("{0} has failed")]
+    TaskFailed(Object),
+    #[error("timed out waiting for {0} to complete")]
+    TaskTimeout(Object),
+    #[error("missing permissions: {0}")]
+    AccessDenied(String),
+    #[error("{0}")]
+    Invalid(invalid::InvalidObject),
+    #[error("{1}\n  defined at {0}")]
+    Located(String, Box<Error>),
+    #[error("{0} is defined twice, {1}")]
+    Duplicate(Object, String),
     #[error("json error: {0}")]
     Json(#[from] serde_json::Error),
     #[error("kube error: {0}")]
@@ -68,6 +130,29 @@ fn make_url(namespace: &str, object: &Object, types: &RuntimeTypeData) -> String
     )
 }
 
+/// Get object by url, returns None if object doesn't exists
+async fn get(client: Client, url: &str) -> Result<Option<Value>> {
+    let req = http::Request::get(url)
+        .header("Accept", "application/json")
+        .body(vec![])
+        .map_err(kube::Error::HttpError)?;
+
+    match client.request(req).await {
+        Ok(v) => Ok(Some(v)),
+        Err(kube::Error::Api(apierror)) if apierror.code == 404 => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Result of dry-run
+struct DryRun {
+    /// Object already exists
+    existed: bool,
+    /// Apply wouldn't change object, unknown if conflicts were resolved
+    unchanged: bool,
+    conflicts: Vec<report::Conflict>,
+}
+
 /// Perform dry-run with conflict resolution
 async fn apply_internal_resolve_conflicts(
     client: Client,
@@ -76,7 +161,7 @@ async fn apply_internal_resolve_conflicts(
     target: &mut Value,
     types: &RuntimeTypeData,
     conflict_resolver: impl Fn(&str, &Path) -> ResolutionStrategy,
-) -> Result<()> {
+) -> Result<DryRun> {
     let object: Object = serde_json::from_value(target.clone())?;
 
     log::trace!("Dry-run apply for {}", object);
@@ -97,38 +182,36 @@ async fn apply_internal_resolve_conflicts(
         .map_err(kube::Error::HttpError)?;
 
     log::trace!("Loading current obj version");
-    let old_obj: Option<_> = match client.request(get_req).await {
+    let old_obj: Option<Value> = match client.request(get_req).await {
         Ok(v) => Some(v),
         Err(kube::Error::Api(apierror)) if apierror.code == 404 => None,
         Err(e) => return Err(e.into()),
-    }
-    .map(|mut v: Value| {
-        for path in [
-            path!(."metadata"."managedFields"),
-            path!(."metadata"."selfLink"),
-            path!(."metadata"."uid"),
-            path!(."metadata"."resourceVersion"),
-            path!(."metadata"."generation"),
-            path!(."metadata"."creationTimestamp"),
-            path!(."metadata"."annotations.kubectl.kubernetes.io/last-applied-configuration"),
-            path!(."status"),
-        ]
-        .iter()
-        {
-            let _res = v.remove_path(path);
-        }
-        v
-    });
-
-    log::trace!("= {}", serde_json::to_string_pretty(&old_obj).unwrap());
+    };
+
+    log::trace!(
+        "= {}",
+        serde_json::to_string_pretty(&old_obj.as_ref().map(|v| {
+            let mut v = redacted(v);
+            unchanged::strip_server_fields(&mut v);
+            v
+        }))
+        .unwrap()
+    );
 
     log::trace!("Running dry-run");
-    match client.request(patch_req).await {
-        Ok(v) => {
-            let _result: Value = v;
-            return Ok(());
+    let mut dry_run = DryRun {
+        existed: old_obj.is_some(),
+        unchanged: false,
+        conflicts: Vec::new(),
+    };
+    match invalid::request_apply(client, patch_req, &object, target).await {
+        Ok(result) => {
+            dry_run.unchanged = old_obj
+                .map(|old| unchanged::is_unchanged(&old, &result, manager))
+                .unwrap_or(false);
+            return Ok(dry_run);
         }
-        Err(kube::Error::Api(apierror)) if apierror.code == 409 => {
+        Err(Error::Kube(kube::Error::Api(apierror))) if apierror.code == 409 => {
             let mut removed_paths = Vec::<PathBuf>::new();
             log::warn!("{}", apierror.message);
             for conflict in parse::conflict_error_parser::message(&apierror.message).unwrap() {
@@ -143,7 +226,13 @@ async fn apply_internal_resolve_conflicts(
                         continue;
                     }
                     log::trace!("Handling conflict with {} at {}", conflict.0, path);
-                    match conflict_resolver(&conflict.0, &path) {
+                    let strategy = conflict_resolver(&conflict.0, &path);
+                    dry_run.conflicts.extend(report::Conflict::resolved(
+                        &conflict.0,
+                        path.to_string(),
+                        &strategy,
+                    ));
+                    match strategy {
                         ResolutionStrategy::Ignore => {
                             log::trace!("- Ignoring");
                             let mut path: &[Element] = &path;
@@ -167,9 +256,9 @@ async fn apply_internal_resolve_conflicts(
                     }
                 }
             }
-            Ok(())
+            Ok(dry_run)
         }
-        Err(e) => return Err(e.into()),
+        Err(e) => return Err(e),
     }
 }
 
@@ -195,7 +284,7 @@ async fn apply_internal_force(
         .body(serde_json::to_vec(&target)?)
         .map_err(kube::Error::HttpError)?;
 
-    let _result: Value = client.request(req).await?;
+    let _result = invalid::request_apply(client, req, &object, &target).await?;
     Ok(())
 }
 
@@ -217,23 +306,132 @@ async fn remove(client: Client, object: &Object, types: &RuntimeTypeData) -> Res
     Ok(())
 }
 
+/// Additional apply behavior
+#[derive(Default)]
+pub struct ApplyOptions {
+    /// Remove labeled objects, which are missing in target
+    pub prune: bool,
+    /// Apply only subset of target
+    pub selection: Selection,
+    /// How long to wait for Jobs of pre and post phases
+    pub task_timeout: Duration,
+    /// Kinds, which are never pruned
+    pub excluded_kinds: Vec<GroupKind>,
+    /// Fail if some kinds can't be listed for pruning
+    pub strict_access: bool,
+    /// Create missing deployment namespace, and namespaces used by objects
+    pub create_namespaces: bool,
+    /// Where target objects were defined, in the same order as target
+    pub sources: Vec<String>,
+    /// Receivers of deployment events
+    pub events: Sinks,
+    /// Deployment lock, which is checked before every change
+    pub lock: Option<LockState>,
+}
+
+impl ApplyOptions {
+    fn check_lock(&self) -> Result<()> {
+        match &self.lock {
+            Some(lock) => lock.check(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Attach source of object to error, if it is known
+fn locate(source: Option<&String>, error: Error) -> Error {
+    match source {
+        Some(source) => Error::Located(source.clone(), Box::new(error)),
+        None => error,
+    }
+}
+
+/// Record object failure in report, and attach its source
+fn object_error(
+    report: &mut Report,
+    sources: &BTreeMap<Object, &String>,
+    object: &Object,
+    error: Error,
+) -> Error {
+    report.object(object).error = Some(error.to_string());
+    locate(sources.get(object).copied(), error)
+}
+
+/// Objects, created by `hayasaka.alwaysRecreate` or `hayasaka.recreateOnChange`
+/// are recreated if they replace pruned object with the same base name
+fn is_recreation(created: &Object, base_name: &str, pruned: &[Object]) -> bool {
+    // Suffixes, generated in kubersApi.jsonnet: 10 hex digits of spec hash for recreateOnChange,
+    // and deployedAt, i.e `2021-03-28-00-00-00-000` for alwaysRecreate
+    let is_hash = |s: &str| s.len() == 10 && s.chars().all(|c| c.is_ascii_hexdigit());
+    let is_time = |s: &str| {
+        let parts = s.split('-').collect::<Vec<_>>();
+        parts
+            .iter()
+            .map(|p| p.len())
+            .eq([4, 2, 2, 2, 2, 2, 3].iter().copied())
+            && parts.iter().all(|p| p.chars().all(|c| c.is_ascii_digit()))
+    };
+    let is_generated = |name: &str| {
+        matches!(
+            name.strip_prefix(base_name).and_then(|s| s.strip_prefix('-')),
+            Some(suffix) if is_hash(suffix) || is_time(suffix)
+        )
+    };
+    pruned.iter().any(|old| {
+        old.kind == created.kind
+            && old.metadata.namespace == created.metadata.namespace
+            && is_generated(&old.metadata.name)
+    })
+}
+
+/// Move objects from deprecated apis, and refresh cached discovery, if it misses some kind
+pub async fn upgrade_target(
+    client: Client,
+    discovery: &mut Discovery,
+    target: &mut [Value],
+) -> Result<()> {
+    let server_version = apis::server_version(client.clone()).await?;
+    for item in target.iter_mut() {
+        apis::upgrade(item, server_version, discovery);
+    }
+
+    let types = discovery.types();
+    if discovery.is_cached()
+        && target.iter().any(|item| {
+            serde_json::from_value::<ObjectKind>(item.clone())
+                .map(|kind| !types.contains_key(&kind))
+                .unwrap_or(false)
+        })
+    {
+        log::info!("unknown object kind found, discovery cache is outdated");
+        discovery.refresh(client).await?;
+    }
+    Ok(())
+}
+
 pub async fn apply_multi(
     client: Client,
+    discovery: &mut Discovery,
     namespace: &str,
     manager: &str,
     label: (&str, &str),
     mut target: Vec<Value>,
     conflict_resolver: impl Fn(&Object, &str, &Path) -> ResolutionStrategy,
-
-    prune: bool,
+    options: &ApplyOptions,
+    report: &mut Report,
 ) -> Result<()> {
-    let types = find::list_apis(client.clone()).await?;
+    upgrade_target(client.clone(), discovery, &mut target).await?;
+    let types = discovery.types();
 
+    // Every object of deployment, including not selected ones
     let mut created = BTreeSet::new();
+    let mut prepared = Vec::new();
+    let mut sources = BTreeMap::new();
 
-    for item in target.iter_mut() {
-        let unstructured: Object =
-            serde_json::from_value(item.clone()).map_err(Error::ObjectParseFailed)?;
+    for (idx, mut item) in target.into_iter().enumerate() {
+        let source = options.sources.get(idx);
+        let unstructured: Object = serde_json::from_value(item.clone())
+            .map_err(|e| locate(source, Error::ObjectParseFailed(e)))?;
 
         {
             // metadata field should exist, this field is already used while parsing object
@@ -247,7 +445,7 @@ pub async fn apply_multi(
             labels.insert(label.0.to_owned(), json!(label.1));
 
             if !types.contains_key(&unstructured.kind) {
-                return Err(Error::UnknownObjectKind(unstructured.kind));
+                return Err(locate(source, Error::UnknownObjectKind(unstructured.kind)));
             }
             if types.get(&unstructured.kind).unwrap().namespaced
                 && !metadata.contains_key("namespace")
@@ -257,42 +455,250 @@ pub async fn apply_multi(
         };
         let unstructured: Object =
             serde_json::from_value(item.clone()).map_err(Error::ObjectParseFailed)?;
+        if !created.insert(unstructured.clone()) {
+            let (first_idx, first) = prepared
+                .iter()
+                .enumerate()
+                .find(|(_, (object, _))| object == &unstructured)
+                .map(|(idx, (_, value))| (idx, value))
+                .unwrap();
+            let describe = |idx: usize, source: Option<&String>| {
+                source.cloned().unwrap_or_else(|| format!("item #{}", idx))
+            };
+            let mut diff = format_diff(&redacted(first), &redacted(&item));
+            if diff.is_empty() {
+                diff = " definitions are identical".to_owned();
+            }
+            return Err(Error::Duplicate(
+                unstructured.clone(),
+                format!(
+                    "at {} and at {}:{}",
+                    describe(first_idx, sources.get(&unstructured).copied()),
+                    describe(idx, source),
+                    diff
+                ),
+            ));
+        }
+        if let Some(source) = source {
+            sources.insert(unstructured.clone(), source);
+        }
+        report.object(&unstructured).source = source.cloned();
+        prepared.push((unstructured, item));
+    }
+
+    checksum::inject_config_checksums(&mut prepared, &format!("{}/config-checksum", label.0));
+    let autoscaled = hpa::autoscaled_objects(&prepared);
+
+    let mut selected = Vec::new();
+    let mut selected_kinds = BTreeSet::new();
+    let mut skipped = 0;
+    for (unstructured, item) in prepared {
+        if !options.selection.is_selected(&item) {
+            log::info!("skipping {}", unstructured);
+            report.set_action(&unstructured, report::Action::Skipped);
+            skipped += 1;
+            continue;
+        }
+        selected_kinds.insert(unstructured.kind.clone());
+        selected.push((unstructured, item));
+    }
+    if skipped != 0 {
+        log::warn!("skipped {} objects not matching selectors", skipped);
+    }
 
-        apply_internal_resolve_conflicts(
+    access::check_access(
+        client.clone(),
+        discovery,
+        &types,
+        &selected,
+        options.prune,
+        options.strict_access,
+    )
+    .await?;
+
+    if options.create_namespaces {
+        options.check_lock()?;
+        namespaces::create_namespaces(client.clone(), &types, namespace, label, &selected).await?;
+    }
+
+    let base_name_annotation = format!("{}/base-name", label.0);
+    let mut existing = BTreeSet::new();
+    let mut unchanged = BTreeSet::new();
+    progress::stage(progress::Stage::DryRun, selected.len());
+    for (unstructured, item) in selected.iter_mut() {
+        progress::start(unstructured);
+        if autoscaled.contains(unstructured) {
+            let live = get(client.clone(), &make_url(namespace, unstructured, &types)).await?;
+            hpa::adjust_replicas(unstructured, item, live.as_ref(), manager);
+        }
+
+        let started = Instant::now();
+        let dry_run = apply_internal_resolve_conflicts(
             client.clone(),
             &namespace,
             &manager,
             item,
             &types,
-            |manager, path| conflict_resolver(&unstructured, manager, path),
+            |manager, path| conflict_resolver(unstructured, manager, path),
         )
-        .await?;
-
-        created.insert(unstructured);
+        .await
+        .map_err(|e| object_error(report, &sources, unstructured, e))?;
+        if dry_run.existed {
+            existing.insert(unstructured.clone());
+        }
+        if dry_run.unchanged {
+            unchanged.insert(unstructured.clone());
+        }
+        let entry = report.object(unstructured);
+        entry.conflicts = dry_run.conflicts;
+        entry.add_duration(started.elapsed());
+        progress::finished(unstructured);
     }
 
-    for item in target {
-        apply_internal_force(client.clone(), &namespace, &manager, item, &types).await?;
+    let phase_annotation = format!("{}/phase", label.0);
+    let mut phased = Vec::new();
+    for (unstructured, item) in selected {
+        let phase = Phase::of(&unstructured, &item, &phase_annotation)
+            .map_err(|e| object_error(report, &sources, &unstructured, e))?;
+        phased.push((phase, unstructured, item));
     }
-
-    if prune {
-        let found = find::find_all_labeled_items(client.clone(), label).await?;
-        let to_remove = found.difference(&created);
-
-        for item in to_remove {
-            // Endpoints copies Service labels
-            if item.kind.api_version == "v1" && item.kind.kind == "Endpoints" {
-                continue;
-            } else if item.kind.api_version == "discovery.k8s.io/v1beta1"
-                && item.kind.kind == "EndpointSlice"
-            {
+    for phase in Phase::ALL.iter() {
+        let mut tasks = Vec::new();
+        let in_phase = phased
+            .iter()
+            .filter(|(p, _, _)| p == phase)
+            .collect::<Vec<_>>();
+        progress::stage(progress::Stage::Apply, in_phase.len());
+        for (_, unstructured, item) in in_phase {
+            if phase != &Phase::Main && phase::is_task(unstructured) {
+                tasks.push(unstructured);
+            }
+            if unchanged.contains(unstructured) {
+                log::debug!("{} is unchanged", unstructured);
+                report.set_action(unstructured, report::Action::Unchanged);
+                progress::finished(unstructured);
                 continue;
             }
+            options.check_lock()?;
+            progress::start(unstructured);
+            let started = Instant::now();
+            apply_internal_force(client.clone(), namespace, manager, item.clone(), &types)
+                .await
+                .map_err(|e| object_error(report, &sources, unstructured, e))?;
+            report.object(unstructured).add_duration(started.elapsed());
+            report.set_action(
+                unstructured,
+                if existing.contains(unstructured) {
+                    report::Action::Updated
+                } else {
+                    report::Action::Created
+                },
+            );
+            progress::finished(unstructured);
+        }
+        if !tasks.is_empty() {
+            progress::stage(progress::Stage::Wait, tasks.len());
+        }
+        for task in tasks {
+            options.check_lock()?;
+            progress::start(task);
+            let started = Instant::now();
+            phase::wait_completed(client.clone(), task, &types, options.task_timeout)
+                .await
+                .map_err(|e| object_error(report, &sources, task, e))?;
+            report.object(task).add_duration(started.elapsed());
+            progress::finished(task);
+        }
+        log::debug!("{} phase is done", phase);
+    }
 
+    let mut pruned = Vec::new();
+    if options.prune {
+        progress::stage(progress::Stage::Prune, 0);
+        let found = find::find_all_labeled_items(client.clone(), discovery, label).await?;
+        let to_remove = found
+            .difference(&created)
+            .filter(|item| !exclude::is_excluded(&item.kind, &options.excluded_kinds))
+            .filter(|item| {
+                if options.selection.is_partial() && !selected_kinds.contains(&item.kind) {
+                    log::info!("not pruning {}, kind is not selected", item);
+                    return false;
+                }
+                true
+            })
+            .collect::<Vec<_>>();
+
+        progress::stage(progress::Stage::Prune, to_remove.len());
+        for item in to_remove {
+            options.check_lock()?;
             log::warn!("pruning {}", item);
-            remove(client.clone(), &item, &types).await?
+            progress::start(item);
+            let started = Instant::now();
+            remove(client.clone(), &item, &types)
+                .await
+                .map_err(|e| object_error(report, &sources, item, e))?;
+            report.object(item).add_duration(started.elapsed());
+            report.set_action(item, report::Action::Pruned);
+            progress::finished(item);
+            pruned.push(item.clone());
+        }
+        if !pruned.is_empty() {
+            options
+                .events
+                .emit(&Event {
+                    objects: pruned.iter().map(ToString::to_string).collect(),
+                    ..Event::new(EventKind::Pruned, label.1)
+                })
+                .await;
+        }
+    }
+
+    for (_, unstructured, item) in &phased {
+        let base_name = match item["metadata"]["annotations"][&base_name_annotation].as_str() {
+            Some(v) => v,
+            None => continue,
+        };
+        let entry = report.object(unstructured);
+        if entry.action == Some(report::Action::Created)
+            && is_recreation(unstructured, base_name, &pruned)
+        {
+            entry.action = Some(report::Action::Recreated);
         }
     }
 
+    if options.selection.is_partial() {
+        log::info!("partial deploy, revision is not recorded");
+        return Ok(());
+    }
+    options.check_lock()?;
+    match revision::store_revision(client.clone(), namespace, manager, label, &created).await {
+        Ok(number) => {
+            log::info!("deployed revision {}", number);
+            report.revision = Some(number);
+        }
+        Err(e) => log::warn!("failed to store revision: {}", e),
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::find::tests::object;
+
+    #[test]
+    fn recreation() {
+        let created = object("batch/v1", "Job", "migrate-0123456789");
+        let replaces =
+            |name| is_recreation(&created, "migrate", &[object("batch/v1", "Job", name)]);
+        // recreateOnChange
+        assert!(replaces("migrate-abcdef0123"));
+        // alwaysRecreate
+        assert!(replaces("migrate-2021-03-28-00-00-00-000"));
+        assert!(!replaces("migrate-2021-03-28"));
+        assert!(!replaces("migrate-old"));
+        assert!(!replaces("migrate-abcdef0123-x"));
+        assert!(!replaces("migrate"));
+    }
+}
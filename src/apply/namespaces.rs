@@ -0,0 +1,113 @@
+use super::{
+    find::{Object, ObjectKind, ObjectList, ObjectLocation, RuntimeTypeData},
+    get, Result,
+};
+use kube::Client;
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+
+/// Label of namespaces, created by hayasaka
+///
+/// Deployment label is not used here, because then such namespace (with all its contents)
+/// would be pruned, unless it is also rendered
+fn owner_label(label: (&str, &str)) -> String {
+    format!("{}/namespace-of", label.0)
+}
+
+/// Create missing namespaces, which are going to be used by deployment
+///
+/// Namespace of cluster-scoped objects is ignored, as apiserver ignores it too
+pub async fn create_namespaces(
+    client: Client,
+    types: &RuntimeTypeData,
+    namespace: &str,
+    label: (&str, &str),
+    target: &[(Object, Value)],
+) -> Result<()> {
+    let mut namespaces = BTreeSet::new();
+    namespaces.insert(namespace.to_owned());
+    for (object, _) in target {
+        if !types
+            .get(&object.kind)
+            .map(|t| t.namespaced)
+            .unwrap_or(false)
+        {
+            continue;
+        }
+        if let Some(namespace) = &object.metadata.namespace {
+            namespaces.insert(namespace.clone());
+        }
+    }
+
+    for namespace in namespaces {
+        create_namespace(client.clone(), &namespace, label).await?;
+    }
+
+    Ok(())
+}
+
+/// Create namespace, if it is missing
+///
+/// Deployment namespace should be created before deployment lock is taken,
+/// because lock lease is stored in it
+pub async fn create_namespace(client: Client, namespace: &str, label: (&str, &str)) -> Result<()> {
+    if get(client.clone(), &format!("/api/v1/namespaces/{}", namespace))
+        .await?
+        .is_some()
+    {
+        return Ok(());
+    }
+    log::warn!("creating namespace {}", namespace);
+
+    let req = http::Request::post("/api/v1/namespaces")
+        .header("Accept", "application/json")
+        .header("Content-Type", "application/json")
+        .body(serde_json::to_vec(&json!({
+            "apiVersion": "v1",
+            "kind": "Namespace",
+            "metadata": {
+                "name": namespace,
+                "labels": {
+                    owner_label(label): label.1,
+                },
+            },
+        }))?)
+        .map_err(kube::Error::HttpError)?;
+    match client.request::<Value>(req).await {
+        Ok(_) => Ok(()),
+        // Created by someone else in the meantime
+        Err(kube::Error::Api(apierror)) if apierror.code == 409 => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Find namespaces, which were created by hayasaka for deployment
+pub async fn find_created_namespaces(
+    client: Client,
+    label: (&str, &str),
+) -> Result<BTreeSet<Object>> {
+    let req = http::Request::get(&format!(
+        "/api/v1/namespaces?labelSelector={}={}",
+        owner_label(label),
+        label.1
+    ))
+    .header("Accept", "application/json")
+    .body(vec![])
+    .map_err(kube::Error::HttpError)?;
+    let list: ObjectList = client.request(req).await?;
+
+    Ok(list
+        .items
+        .into_iter()
+        .map(|item| Object {
+            kind: ObjectKind {
+                api_version: "v1".to_owned(),
+                kind: "Namespace".to_owned(),
+            },
+            metadata: ObjectLocation {
+                name: item.metadata.location.name,
+                namespace: None,
+            },
+        })
+        .collect())
+}
@@ -0,0 +1,61 @@
+use super::find::ObjectKind;
+
+/// Kinds in order they should be created in, objects of later kinds may depend on
+/// objects of earlier kinds
+///
+/// Same order is used by helm
+const INSTALL_ORDER: &[&str] = &[
+    "Namespace",
+    "NetworkPolicy",
+    "ResourceQuota",
+    "LimitRange",
+    "PodSecurityPolicy",
+    "PodDisruptionBudget",
+    "ServiceAccount",
+    "Secret",
+    "ConfigMap",
+    "StorageClass",
+    "PersistentVolume",
+    "PersistentVolumeClaim",
+    "CustomResourceDefinition",
+    "ClusterRole",
+    "ClusterRoleBinding",
+    "Role",
+    "RoleBinding",
+    "Service",
+    "DaemonSet",
+    "Pod",
+    "ReplicationController",
+    "ReplicaSet",
+    "Deployment",
+    "HorizontalPodAutoscaler",
+    "StatefulSet",
+    "Job",
+    "CronJob",
+    "Ingress",
+    "APIService",
+];
+
+/// Position of kind in install order, unknown kinds (i.e custom resources) go last,
+/// because they may depend on any builtin object
+pub fn install_order(kind: &ObjectKind) -> usize {
+    INSTALL_ORDER
+        .iter()
+        .position(|k| *k == kind.kind)
+        .unwrap_or_else(|| INSTALL_ORDER.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::find::tests::kind;
+
+    #[test]
+    fn custom_resources_last() {
+        assert!(install_order(&kind("v1", "Namespace")) < install_order(&kind("v1", "Secret")));
+        assert!(
+            install_order(&kind("apiextensions.k8s.io/v1", "CustomResourceDefinition"))
+                < install_order(&kind("cert-manager.io/v1", "Certificate"))
+        );
+    }
+}
@@ -0,0 +1,220 @@
+use super::{
+    find::{Object, RuntimeTypeData},
+    get, make_url, Error, Result,
+};
+use crate::progress;
+use kube::Client;
+use serde_json::Value;
+use std::{
+    fmt::{self, Display},
+    time::{Duration, Instant},
+};
+use tokio::time::sleep;
+
+/// Deployment phase, objects of every phase are applied after objects of previous one,
+/// and Jobs of pre and post phases should complete before going on
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Phase {
+    Pre,
+    Main,
+    Post,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 3] = [Phase::Pre, Phase::Main, Phase::Post];
+
+    /// Read phase from object annotation, objects without annotation belong to main phase
+    pub fn of(object: &Object, value: &Value, annotation: &str) -> Result<Self> {
+        Ok(
+            match value["metadata"]["annotations"][annotation].as_str() {
+                None | Some("main") => Self::Main,
+                Some("pre") => Self::Pre,
+                Some("post") => Self::Post,
+                Some(other) => return Err(Error::UnknownPhase(object.clone(), other.to_owned())),
+            },
+        )
+    }
+}
+
+impl Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pre => write!(f, "pre"),
+            Self::Main => write!(f, "main"),
+            Self::Post => write!(f, "post"),
+        }
+    }
+}
+
+/// Objects, which should be waited for in pre and post phases
+pub fn is_task(object: &Object) -> bool {
+    (object.kind.api_version.starts_with("batch/") && object.kind.kind == "Job")
+        || (object.kind.api_version == "v1" && object.kind.kind == "Pod")
+}
+
+/// Some(true) if task has succeeded, Some(false) if failed, None if it is still running
+fn completion(object: &Object, value: &Value) -> Option<bool> {
+    if object.kind.kind == "Pod" {
+        return match value["status"]["phase"].as_str() {
+            Some("Succeeded") => Some(true),
+            Some("Failed") => Some(false),
+            _ => None,
+        };
+    }
+    let conditions = value["status"]["conditions"].as_array()?;
+    let has_condition = |ty: &str| {
+        conditions
+            .iter()
+            .any(|c| c["type"] == ty && c["status"] == "True")
+    };
+    if has_condition("Complete") {
+        Some(true)
+    } else if has_condition("Failed") {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// Print last log lines of every container of failed task
+async fn print_logs(client: Client, object: &Object) -> Result<()> {
+    let namespace = object.metadata.namespace.clone().unwrap_or_default();
+    let pods = if object.kind.kind == "Pod" {
+        vec![object.metadata.name.clone()]
+    } else {
+        let list = get(
+            client.clone(),
+            &format!(
+                "/api/v1/namespaces/{}/pods?labelSelector=job-name={}",
+                namespace, object.metadata.name
+            ),
+        )
+        .await?
+        .unwrap_or_default();
+        list["items"]
+            .as_array()
+            .map(|items| {
+                items
+                    .iter()
+                    .filter_map(|pod| pod["metadata"]["name"].as_str())
+                    .map(ToOwned::to_owned)
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+
+    for pod in pods {
+        let url = format!("/api/v1/namespaces/{}/pods/{}", namespace, pod);
+        let value = match get(client.clone(), &url).await? {
+            Some(v) => v,
+            None => continue,
+        };
+        let empty = vec![];
+        let containers = value["spec"]["initContainers"]
+            .as_array()
+            .unwrap_or(&empty)
+            .iter()
+            .chain(value["spec"]["containers"].as_array().unwrap_or(&empty));
+        for container in containers {
+            let container = container["name"].as_str().unwrap_or_default();
+            let req = http::Request::get(&format!(
+                "{}/log?container={}&tailLines=100",
+                url, container
+            ))
+            .body(vec![])
+            .map_err(kube::Error::HttpError)?;
+            match client.request_text(req).await {
+                Ok(logs) => {
+                    progress::print(&format!("--- logs of {}/{}\n{}", pod, container, logs))
+                }
+                Err(e) => log::warn!("failed to get logs of {}/{}: {}", pod, container, e),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Wait for Job or Pod to complete, printing its logs on failure
+pub async fn wait_completed(
+    client: Client,
+    object: &Object,
+    types: &RuntimeTypeData,
+    timeout: Duration,
+) -> Result<()> {
+    let url = make_url("", object, types);
+    let deadline = Instant::now() + timeout;
+    log::info!("waiting for {} to complete", object);
+
+    loop {
+        let value = get(client.clone(), &url)
+            .await?
+            .ok_or_else(|| Error::TaskFailed(object.clone()))?;
+        let error = match completion(object, &value) {
+            Some(true) => return Ok(()),
+            Some(false) => Error::TaskFailed(object.clone()),
+            None if Instant::now() >= deadline => Error::TaskTimeout(object.clone()),
+            None => {
+                sleep(Duration::from_secs(2)).await;
+                continue;
+            }
+        };
+        // Logs are only a hint, task error is more important
+        if let Err(e) = print_logs(client.clone(), object).await {
+            log::warn!("failed to get logs of {}: {}", object, e);
+        }
+        return Err(error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::find::tests::object;
+    use serde_json::json;
+
+    #[test]
+    fn phase_annotation() {
+        let job = object("batch/v1", "Job", "migrate");
+        let phase = |annotations: Value| {
+            Phase::of(
+                &job,
+                &json!({"metadata": {"annotations": annotations}}),
+                "hayasaka.delta.rocks/phase",
+            )
+        };
+        assert_eq!(phase(json!({})).unwrap(), Phase::Main);
+        assert_eq!(
+            phase(json!({"hayasaka.delta.rocks/phase": "pre"})).unwrap(),
+            Phase::Pre
+        );
+        assert!(phase(json!({"hayasaka.delta.rocks/phase": "later"})).is_err());
+    }
+
+    #[test]
+    fn job_completion() {
+        let job = object("batch/v1", "Job", "migrate");
+        assert_eq!(completion(&job, &json!({"status": {"active": 1}})), None);
+        assert_eq!(
+            completion(
+                &job,
+                &json!({"status": {"conditions": [{"type": "Complete", "status": "True"}]}})
+            ),
+            Some(true)
+        );
+        assert_eq!(
+            completion(
+                &job,
+                &json!({"status": {"conditions": [{"type": "Failed", "status": "True"}]}})
+            ),
+            Some(false)
+        );
+        assert_eq!(
+            completion(
+                &object("v1", "Pod", "migrate"),
+                &json!({"status": {"phase": "Succeeded"}})
+            ),
+            Some(true)
+        );
+    }
+}
@@ -0,0 +1,295 @@
+use super::{
+    discovery::Discovery,
+    find::{api_group, Object},
+    lock::lock_name,
+    phase::{self, Phase},
+    revision::revision_name,
+    Error, Result,
+};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Verbs, allowed on resources of single scope
+#[derive(Default)]
+struct Permissions(BTreeMap<(String, String), BTreeSet<&'static str>>);
+
+impl Permissions {
+    fn allow(&mut self, group: &str, resource: &str, verbs: &[&'static str]) {
+        self.0
+            .entry((group.to_owned(), resource.to_owned()))
+            .or_default()
+            .extend(verbs.iter());
+    }
+
+    /// Rules, resources with the same verbs are grouped together
+    fn rules(&self) -> Vec<Value> {
+        let mut grouped = BTreeMap::<_, Vec<&str>>::new();
+        for ((group, resource), verbs) in &self.0 {
+            grouped
+                .entry((group.as_str(), verbs))
+                .or_default()
+                .push(resource);
+        }
+        grouped
+            .into_iter()
+            .map(|((group, verbs), resources)| {
+                json!({
+                    "apiGroups": [group],
+                    "resources": resources,
+                    "verbs": verbs,
+                })
+            })
+            .collect()
+    }
+}
+
+fn binding(
+    kind: &str,
+    role_kind: &str,
+    name: &str,
+    namespace: Option<&str>,
+    subject: (&str, &str),
+) -> Value {
+    let mut metadata = json!({ "name": name });
+    if let Some(namespace) = namespace {
+        metadata["namespace"] = json!(namespace);
+    }
+    json!({
+        "apiVersion": "rbac.authorization.k8s.io/v1",
+        "kind": kind,
+        "metadata": metadata,
+        "roleRef": {
+            "apiGroup": "rbac.authorization.k8s.io",
+            "kind": role_kind,
+            "name": name,
+        },
+        "subjects": [{
+            "kind": "ServiceAccount",
+            "namespace": subject.0,
+            "name": subject.1,
+        }],
+    })
+}
+
+/// Minimal roles and bindings, needed for ServiceAccount `subject` (namespace, name)
+/// to deploy target
+///
+/// Grants everything, required by access preflight with prune enabled. Delete is only granted
+/// for rendered kinds, objects of kinds removed from deployment should be pruned by someone else
+pub fn generate_rbac(
+    discovery: &Discovery,
+    namespace: &str,
+    label: (&str, &str),
+    target: &[Value],
+    subject: (&str, &str),
+) -> Result<Vec<Value>> {
+    let types = discovery.types();
+    let phase_annotation = format!("{}/phase", label.0);
+
+    let mut cluster = Permissions::default();
+    let mut namespaced = BTreeMap::<String, Permissions>::new();
+    namespaced.entry(namespace.to_owned()).or_default();
+
+    for item in target {
+        let object: Object =
+            serde_json::from_value(item.clone()).map_err(Error::ObjectParseFailed)?;
+        let data = types
+            .get(&object.kind)
+            .ok_or_else(|| Error::UnknownObjectKind(object.kind.clone()))?;
+        let group = object.kind.group();
+        if !data.namespaced {
+            cluster.allow(group, &data.plural, &["get", "patch", "delete"]);
+            continue;
+        }
+        let permissions = namespaced
+            .entry(
+                object
+                    .metadata
+                    .namespace
+                    .clone()
+                    .unwrap_or_else(|| namespace.to_owned()),
+            )
+            .or_default();
+        permissions.allow(group, &data.plural, &["get", "patch", "delete"]);
+        if phase::is_task(&object) && Phase::of(&object, item, &phase_annotation)? != Phase::Main {
+            // Logs of failed tasks, pods of job are found by label
+            permissions.allow("", "pods", &["get", "list"]);
+            permissions.allow("", "pods/log", &["get"]);
+        }
+    }
+
+    // Prune scans every kind in every namespace
+    for resource in discovery
+        .resources
+        .iter()
+        .filter(|r| r.preferred && r.verbs.iter().any(|v| v == "list"))
+    {
+        cluster.allow(
+            api_group(&resource.api_version),
+            &resource.plural,
+            &["list"],
+        );
+    }
+    cluster.allow(
+        "authorization.k8s.io",
+        "selfsubjectaccessreviews",
+        &["create"],
+    );
+
+    let name = format!("hayasaka-{}", label.1);
+    let mut cluster_rules = cluster.rules();
+    cluster_rules.push(json!({
+        "nonResourceURLs": ["/api", "/api/*", "/apis", "/apis/*", "/version"],
+        "verbs": ["get"],
+    }));
+    let mut out = vec![
+        json!({
+            "apiVersion": "rbac.authorization.k8s.io/v1",
+            "kind": "ClusterRole",
+            "metadata": {"name": name},
+            "rules": cluster_rules,
+        }),
+        binding("ClusterRoleBinding", "ClusterRole", &name, None, subject),
+    ];
+
+    for (role_namespace, permissions) in namespaced {
+        let mut rules = permissions.rules();
+        if role_namespace == namespace {
+            rules.push(json!({
+                "apiGroups": [""],
+                "resources": ["configmaps"],
+                "resourceNames": [revision_name(label)],
+                "verbs": ["get", "patch", "delete"],
+            }));
+            // Lease is created by name in body, so resourceNames can't be used for create
+            rules.push(json!({
+                "apiGroups": ["coordination.k8s.io"],
+                "resources": ["leases"],
+                "verbs": ["create"],
+            }));
+            rules.push(json!({
+                "apiGroups": ["coordination.k8s.io"],
+                "resources": ["leases"],
+                "resourceNames": [lock_name(label)],
+                "verbs": ["get", "update", "delete"],
+            }));
+        }
+        out.push(json!({
+            "apiVersion": "rbac.authorization.k8s.io/v1",
+            "kind": "Role",
+            "metadata": {
+                "name": name,
+                "namespace": role_namespace,
+            },
+            "rules": rules,
+        }));
+        out.push(binding(
+            "RoleBinding",
+            "Role",
+            &name,
+            Some(&role_namespace),
+            subject,
+        ));
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::access::{required_access, Access};
+
+    /// Whether any rule of generated roles allows access
+    fn is_granted(rbac: &[Value], access: &Access) -> bool {
+        let contains =
+            |list: &Value, item: &str| list.as_array().into_iter().flatten().any(|v| v == item);
+        rbac.iter()
+            .filter(|role| match role["kind"].as_str() {
+                Some("ClusterRole") => true,
+                Some("Role") => {
+                    access.namespace.as_deref() == role["metadata"]["namespace"].as_str()
+                }
+                _ => false,
+            })
+            .flat_map(|role| role["rules"].as_array().unwrap())
+            .any(|rule| {
+                rule.get("resourceNames").is_none()
+                    && contains(&rule["apiGroups"], &access.group)
+                    && contains(&rule["resources"], &access.resource)
+                    && contains(&rule["verbs"], access.verb)
+            })
+    }
+
+    #[test]
+    fn preflight() {
+        let mut discovery =
+            Discovery::from_kinds(&[("v1", "ConfigMap"), ("apps/v1", "Deployment")]);
+        for resource in &mut discovery.resources {
+            resource.verbs = vec!["list".to_owned()];
+        }
+        let target = vec![
+            json!({"apiVersion": "v1", "kind": "ConfigMap", "metadata": {"name": "a", "namespace": "test"}}),
+            json!({"apiVersion": "apps/v1", "kind": "Deployment", "metadata": {"name": "a", "namespace": "other"}}),
+        ];
+        let rbac = generate_rbac(
+            &discovery,
+            "test",
+            ("hayasaka.delta.rocks", "test"),
+            &target,
+            ("ci", "deployer"),
+        )
+        .unwrap();
+
+        let prepared = target
+            .iter()
+            .map(|v| (serde_json::from_value(v.clone()).unwrap(), v.clone()))
+            .collect::<Vec<(Object, Value)>>();
+        let required = required_access(&discovery, &discovery.types(), &prepared, true);
+        assert_eq!(required.len(), 8);
+        for access in &required {
+            assert!(is_granted(&rbac, access), "{:?} is not granted", access);
+        }
+    }
+
+    #[test]
+    fn roles() {
+        let discovery = Discovery::from_kinds(&[("v1", "ConfigMap"), ("apps/v1", "Deployment")]);
+        let target = vec![
+            json!({"apiVersion": "v1", "kind": "ConfigMap", "metadata": {"name": "a"}}),
+            json!({"apiVersion": "apps/v1", "kind": "Deployment", "metadata": {"name": "a", "namespace": "other"}}),
+        ];
+        let out = generate_rbac(
+            &discovery,
+            "test",
+            ("hayasaka.delta.rocks", "test"),
+            &target,
+            ("ci", "deployer"),
+        )
+        .unwrap();
+
+        let kinds = out
+            .iter()
+            .map(|v| format!("{} {}", v["kind"], v["metadata"]["namespace"]))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                r#""ClusterRole" null"#,
+                r#""ClusterRoleBinding" null"#,
+                r#""Role" "other""#,
+                r#""RoleBinding" "other""#,
+                r#""Role" "test""#,
+                r#""RoleBinding" "test""#,
+            ]
+        );
+        assert_eq!(
+            out[2]["rules"],
+            json!([{
+                "apiGroups": ["apps"],
+                "resources": ["deployments"],
+                "verbs": ["delete", "get", "patch"],
+            }])
+        );
+    }
+}
@@ -0,0 +1,87 @@
+use fieldpath::{FieldpathExt, PathBuf};
+use lazy_static::lazy_static;
+use serde_json::Value;
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::RwLock,
+};
+
+lazy_static! {
+    /// User configured fields, which are redacted in every object
+    static ref PATHS: RwLock<Vec<PathBuf>> = RwLock::new(Vec::new());
+    /// Random per run, so short hashes of low entropy values can't be brute forced
+    static ref SALT: u64 = RandomState::new().build_hasher().finish();
+}
+
+pub fn set_redacted_paths(paths: Vec<PathBuf>) {
+    *PATHS.write().unwrap() = paths;
+}
+
+/// Replacement of sensitive value, hash allows to tell if value was changed during this run
+fn marker(value: &Value) -> Value {
+    let hash = format!("{:x}", md5::compute(format!("{:x}:{}", *SALT, value)));
+    Value::String(format!("<redacted {}>", &hash[..8]))
+}
+
+/// Mask data of Secrets and configured fields of object
+pub fn redact(object: &mut Value) {
+    if object["apiVersion"] == "v1" && object["kind"] == "Secret" {
+        for field in &["data", "stringData"] {
+            if let Some(data) = object.get_mut(field).and_then(Value::as_object_mut) {
+                for value in data.values_mut() {
+                    *value = marker(value);
+                }
+            }
+        }
+    }
+    for path in PATHS.read().unwrap().iter() {
+        if let Ok(value) = object.get_path_mut(path) {
+            *value = marker(value);
+        }
+    }
+}
+
+/// Copy of object, suitable for logs, diffs and reports
+pub fn redacted(object: &Value) -> Value {
+    let mut object = object.clone();
+    redact(&mut object);
+    object
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn secret() {
+        let secret = |password: &str| {
+            redacted(&json!({
+                "apiVersion": "v1",
+                "kind": "Secret",
+                "metadata": {"name": "app"},
+                "data": {"password": password},
+                "stringData": {"user": "admin"},
+            }))
+        };
+        let a = secret("aHVudGVyMg==");
+        assert!(!a.to_string().contains("aHVudGVyMg=="));
+        assert!(!a.to_string().contains("admin"));
+        assert_eq!(a["metadata"]["name"], "app");
+        assert_eq!(a, secret("aHVudGVyMg=="));
+        assert_ne!(a["data"], secret("cGFzc3dvcmQ=")["data"]);
+    }
+
+    #[test]
+    fn paths() {
+        set_redacted_paths(vec![".spec.token".parse().unwrap()]);
+        let object =
+            redacted(&json!({"kind": "Runner", "spec": {"token": "t0ken", "replicas": 1}}));
+        assert!(object["spec"]["token"]
+            .as_str()
+            .unwrap()
+            .starts_with("<redacted "));
+        assert_eq!(object["spec"]["replicas"], 1);
+    }
+}
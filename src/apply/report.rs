@@ -0,0 +1,234 @@
+use super::{find::Object, Error, ResolutionStrategy};
+use crate::progress;
+use chrono::{SecondsFormat, Utc};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+/// What was done with object
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Created,
+    Updated,
+    /// Apply wouldn't change anything, and was skipped
+    Unchanged,
+    /// Created with new name, replacing pruned object, see `hayasaka.alwaysRecreate`
+    Recreated,
+    Pruned,
+    /// Not matching selection
+    Skipped,
+}
+
+impl Action {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Created => "created",
+            Self::Updated => "updated",
+            Self::Unchanged => "unchanged",
+            Self::Recreated => "recreated",
+            Self::Pruned => "pruned",
+            Self::Skipped => "skipped",
+        }
+    }
+}
+
+/// Resolved field manager conflict
+#[derive(Serialize)]
+pub struct Conflict {
+    pub manager: String,
+    pub path: String,
+    pub strategy: &'static str,
+}
+
+impl Conflict {
+    /// None for failed resolution, which is reported as error
+    pub fn resolved(manager: &str, path: String, strategy: &ResolutionStrategy) -> Option<Self> {
+        let strategy = match strategy {
+            ResolutionStrategy::Ignore => "ignore",
+            ResolutionStrategy::Share => "share",
+            ResolutionStrategy::Force => "force",
+            ResolutionStrategy::Error(_) => return None,
+        };
+        Some(Self {
+            manager: manager.to_owned(),
+            path,
+            strategy,
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct ObjectReport {
+    #[serde(flatten)]
+    pub object: Object,
+    /// Where object was defined, i.e `main.jsonnet:12 .app.deployment`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// None if deployment has failed before reaching this object
+    pub action: Option<Action>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub conflicts: Vec<Conflict>,
+    /// Time spent applying, waiting or removing object
+    #[serde(rename = "durationMs")]
+    pub duration_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ObjectReport {
+    pub fn add_duration(&mut self, duration: Duration) {
+        self.duration_ms += duration.as_millis() as u64;
+    }
+}
+
+/// Result of deployment, which determines process exit code
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Outcome {
+    /// Exit code 0
+    Succeeded,
+    /// Cluster config can't be loaded, discovery has failed, or deployment lock can't be acquired,
+    /// nothing was applied, exit code 1
+    SetupFailed,
+    /// Jsonnet evaluation has failed, nothing was applied, exit code 3
+    EvaluationFailed,
+    /// Apiserver has rejected some objects, or deployment was interrupted, exit code 4
+    ApplyFailed,
+    /// Task of pre or post phase has failed or timed out, exit code 5
+    WaitFailed,
+}
+
+impl Outcome {
+    pub fn of(error: &Error) -> Self {
+        match error {
+            Error::Located(_, error) => Self::of(error),
+            Error::TaskFailed(_) | Error::TaskTimeout(_) => Self::WaitFailed,
+            _ => Self::ApplyFailed,
+        }
+    }
+
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Self::Succeeded => 0,
+            Self::SetupFailed => 1,
+            Self::EvaluationFailed => 3,
+            Self::ApplyFailed => 4,
+            Self::WaitFailed => 5,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Summary {
+    pub outcome: Outcome,
+    #[serde(rename = "exitCode")]
+    pub exit_code: i32,
+    /// Number of objects per action
+    pub actions: BTreeMap<Action, usize>,
+    #[serde(rename = "startedAt")]
+    pub started_at: String,
+    #[serde(rename = "durationMs")]
+    pub duration_ms: u64,
+}
+
+/// Machine readable description of deployment
+#[derive(Serialize)]
+pub struct Report {
+    /// Stored revision, absent for partial or failed deployment
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revision: Option<u64>,
+    pub objects: Vec<ObjectReport>,
+    pub errors: Vec<String>,
+    pub summary: Option<Summary>,
+    #[serde(skip)]
+    index: BTreeMap<Object, usize>,
+    #[serde(skip)]
+    started: Instant,
+    #[serde(skip)]
+    started_at: String,
+}
+
+impl Default for Report {
+    fn default() -> Self {
+        Self {
+            revision: None,
+            objects: Vec::new(),
+            errors: Vec::new(),
+            summary: None,
+            index: BTreeMap::new(),
+            started: Instant::now(),
+            started_at: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        }
+    }
+}
+
+impl Report {
+    /// Entry of object, created on first access
+    pub fn object(&mut self, object: &Object) -> &mut ObjectReport {
+        let objects = &mut self.objects;
+        let idx = *self.index.entry(object.clone()).or_insert_with(|| {
+            objects.push(ObjectReport {
+                object: object.clone(),
+                source: None,
+                action: None,
+                conflicts: Vec::new(),
+                duration_ms: 0,
+                error: None,
+            });
+            objects.len() - 1
+        });
+        &mut self.objects[idx]
+    }
+
+    pub fn set_action(&mut self, object: &Object, action: Action) {
+        self.object(object).action = Some(action);
+        progress::count(action.name(), object);
+    }
+
+    pub fn finish(&mut self, outcome: Outcome) {
+        let mut actions = BTreeMap::new();
+        for action in self.objects.iter().filter_map(|o| o.action) {
+            *actions.entry(action).or_insert(0) += 1;
+        }
+        self.summary = Some(Summary {
+            outcome,
+            exit_code: outcome.exit_code(),
+            actions,
+            started_at: self.started_at.clone(),
+            duration_ms: self.started.elapsed().as_millis() as u64,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::find::tests::object;
+    use serde_json::json;
+
+    #[test]
+    fn summary() {
+        let object = object("v1", "ConfigMap", "app");
+        let mut report = Report::default();
+        report.set_action(&object, Action::Created);
+        report.object(&object).conflicts.extend(Conflict::resolved(
+            "kubectl",
+            ".data.key".to_owned(),
+            &ResolutionStrategy::Force,
+        ));
+        report.finish(Outcome::of(&Error::TaskFailed(object)));
+
+        let value = serde_json::to_value(&report).unwrap();
+        assert_eq!(value["objects"].as_array().unwrap().len(), 1);
+        assert_eq!(
+            value["objects"][0]["conflicts"],
+            json!([{"manager": "kubectl", "path": ".data.key", "strategy": "force"}])
+        );
+        assert_eq!(value["summary"]["outcome"], "waitFailed");
+        assert_eq!(value["summary"]["exitCode"], 5);
+        assert_eq!(value["summary"]["actions"], json!({"created": 1}));
+    }
+}
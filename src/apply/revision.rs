@@ -0,0 +1,111 @@
+use super::{find::Object, get, Result};
+use chrono::{SecondsFormat, Utc};
+use kube::Client;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+
+/// Record of last successful deployment, stored in ConfigMap in deployment namespace
+///
+/// ConfigMap is not labeled as part of deployment, so it wouldn't be pruned
+#[derive(Serialize)]
+pub struct Revision {
+    pub number: u64,
+    #[serde(rename = "deployedAt")]
+    pub deployed_at: String,
+    #[serde(skip)]
+    pub objects: BTreeSet<Object>,
+}
+
+/// Name of revision ConfigMap
+pub fn revision_name(label: (&str, &str)) -> String {
+    format!("hayasaka-revision-{}", label.1)
+}
+
+fn revision_url(namespace: &str, label: (&str, &str)) -> String {
+    format!(
+        "/api/v1/namespaces/{}/configmaps/{}",
+        namespace,
+        revision_name(label)
+    )
+}
+
+/// Load last stored revision
+pub async fn load_revision(
+    client: Client,
+    namespace: &str,
+    label: (&str, &str),
+) -> Result<Option<Revision>> {
+    let value = match get(client, &revision_url(namespace, label)).await? {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    let data = &value["data"];
+    Ok(Some(Revision {
+        number: data["revision"]
+            .as_str()
+            .and_then(|r| r.parse().ok())
+            .unwrap_or(0),
+        deployed_at: data["deployedAt"].as_str().unwrap_or_default().to_owned(),
+        objects: serde_json::from_str(data["objects"].as_str().unwrap_or("[]"))?,
+    }))
+}
+
+/// Store next revision, consisting of specified objects
+pub async fn store_revision(
+    client: Client,
+    namespace: &str,
+    manager: &str,
+    label: (&str, &str),
+    objects: &BTreeSet<Object>,
+) -> Result<u64> {
+    let number = load_revision(client.clone(), namespace, label)
+        .await?
+        .map(|r| r.number)
+        .unwrap_or(0)
+        + 1;
+
+    let body = json!({
+        "apiVersion": "v1",
+        "kind": "ConfigMap",
+        "metadata": {
+            "name": revision_name(label),
+            "namespace": namespace,
+            "labels": {
+                format!("{}/revision-of", label.0): label.1,
+            },
+        },
+        "data": {
+            "revision": number.to_string(),
+            "deployedAt": Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            "objects": serde_json::to_string(objects)?,
+        },
+    });
+
+    let req = http::Request::patch(&format!(
+        "{}?fieldManager={}&force=true",
+        revision_url(namespace, label),
+        manager
+    ))
+    .header("Accept", "application/json")
+    .header("Content-Type", "application/apply-patch+yaml")
+    .body(serde_json::to_vec(&body)?)
+    .map_err(kube::Error::HttpError)?;
+
+    let _result: Value = client.request(req).await?;
+    Ok(number)
+}
+
+/// Remove stored revision, if any
+pub async fn remove_revision(client: Client, namespace: &str, label: (&str, &str)) -> Result<()> {
+    let req = http::Request::delete(&revision_url(namespace, label))
+        .header("Accept", "application/json")
+        .body(vec![])
+        .map_err(kube::Error::HttpError)?;
+
+    match client.request::<Value>(req).await {
+        Ok(_) => Ok(()),
+        Err(kube::Error::Api(apierror)) if apierror.code == 404 => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,176 @@
+use serde_json::Value;
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum SelectorError {
+    #[error("expected key=glob, got {0:?}")]
+    MissingValue(String),
+    #[error("unknown selector key {0:?}, expected apiVersion, kind, namespace, name or label:<key>")]
+    UnknownKey(String),
+}
+
+/// Match string against glob pattern, supporting `*` and `?`
+fn glob_matches(pattern: &str, value: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let value = value.chars().collect::<Vec<_>>();
+
+    let (mut p, mut v) = (0, 0);
+    // Position of last seen star in pattern, and position in value it currently matches to
+    let mut backtrack = None;
+    while v < value.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == value[v]) {
+            p += 1;
+            v += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, v));
+            p += 1;
+        } else if let Some((star_p, star_v)) = backtrack {
+            p = star_p + 1;
+            v = star_v + 1;
+            backtrack = Some((star_p, star_v + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[derive(Debug, PartialEq)]
+enum Condition {
+    ApiVersion(String),
+    Kind(String),
+    Namespace(String),
+    Name(String),
+    Label(String, String),
+}
+
+impl Condition {
+    fn matches(&self, value: &Value) -> bool {
+        let str_at = |value: &Value| value.as_str().unwrap_or("").to_owned();
+        match self {
+            Self::ApiVersion(glob) => glob_matches(glob, &str_at(&value["apiVersion"])),
+            Self::Kind(glob) => glob_matches(glob, &str_at(&value["kind"])),
+            Self::Namespace(glob) => {
+                glob_matches(glob, &str_at(&value["metadata"]["namespace"]))
+            }
+            Self::Name(glob) => glob_matches(glob, &str_at(&value["metadata"]["name"])),
+            Self::Label(key, glob) => match value["metadata"]["labels"][key].as_str() {
+                Some(label) => glob_matches(glob, label),
+                None => false,
+            },
+        }
+    }
+}
+
+/// Object selector, i.e `kind=ConfigMap,name=app-*,label:tier=web`
+///
+/// Object matches selector if it matches every condition
+#[derive(Debug, PartialEq)]
+pub struct Selector(Vec<Condition>);
+
+impl Selector {
+    pub fn matches(&self, value: &Value) -> bool {
+        self.0.iter().all(|c| c.matches(value))
+    }
+}
+
+impl FromStr for Selector {
+    type Err = SelectorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut out = Vec::new();
+        for part in s.split(',') {
+            let eq = part
+                .find('=')
+                .ok_or_else(|| SelectorError::MissingValue(part.to_owned()))?;
+            let (key, glob) = (&part[..eq], part[eq + 1..].to_owned());
+            out.push(match key {
+                "apiVersion" => Condition::ApiVersion(glob),
+                "kind" => Condition::Kind(glob),
+                "namespace" => Condition::Namespace(glob),
+                "name" => Condition::Name(glob),
+                key if key.starts_with("label:") => {
+                    Condition::Label(key["label:".len()..].to_owned(), glob)
+                }
+                key => return Err(SelectorError::UnknownKey(key.to_owned())),
+            });
+        }
+        Ok(Self(out))
+    }
+}
+
+/// Subset of deployment objects to apply
+#[derive(Default)]
+pub struct Selection {
+    /// If not empty - only objects matching any of these selectors are applied
+    pub only: Vec<Selector>,
+    /// Objects matching any of these selectors are skipped
+    pub exclude: Vec<Selector>,
+}
+
+impl Selection {
+    /// Is only part of deployment is going to be applied
+    pub fn is_partial(&self) -> bool {
+        !self.only.is_empty() || !self.exclude.is_empty()
+    }
+
+    pub fn is_selected(&self, value: &Value) -> bool {
+        (self.only.is_empty() || self.only.iter().any(|s| s.matches(value)))
+            && !self.exclude.iter().any(|s| s.matches(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn glob() {
+        assert!(glob_matches("app-*", "app-config"));
+        assert!(glob_matches("*-config", "app-config"));
+        assert!(glob_matches("a*b*c", "aXXbYYbc"));
+        assert!(glob_matches("v?", "v1"));
+        assert!(glob_matches("*", ""));
+        assert!(!glob_matches("app-*", "web-config"));
+        assert!(!glob_matches("v?", "v1beta1"));
+    }
+
+    #[test]
+    fn selection() {
+        let object = json!({
+            "apiVersion": "v1",
+            "kind": "ConfigMap",
+            "metadata": {
+                "name": "app-config",
+                "namespace": "test",
+                "labels": {"tier": "web"},
+            },
+        });
+
+        let selection = Selection {
+            only: vec!["kind=ConfigMap,name=app-*".parse().unwrap()],
+            exclude: vec![],
+        };
+        assert!(selection.is_selected(&object));
+
+        let selection = Selection {
+            only: vec![],
+            exclude: vec!["label:tier=web".parse().unwrap()],
+        };
+        assert!(!selection.is_selected(&object));
+
+        let selection = Selection {
+            only: vec!["namespace=other".parse().unwrap()],
+            exclude: vec![],
+        };
+        assert!(!selection.is_selected(&object));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!("kind".parse::<Selector>().is_err());
+        assert!("color=red".parse::<Selector>().is_err());
+    }
+}
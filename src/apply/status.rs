@@ -0,0 +1,78 @@
+use super::{
+    default_excluded_kinds,
+    discovery::Discovery,
+    find::Object,
+    find_deployed, get,
+    health::{health, Health},
+    make_url,
+    revision::{load_revision, Revision},
+    Result,
+};
+use kube::Client;
+use serde::Serialize;
+use serde_json::Value;
+
+/// Status of single deployed object
+#[derive(Serialize)]
+pub struct ObjectStatus {
+    #[serde(flatten)]
+    pub object: Object,
+    #[serde(rename = "creationTimestamp")]
+    pub created: Option<String>,
+    /// Manager, which made latest change to object
+    pub manager: Option<String>,
+    pub health: Health,
+    /// Is this object part of last applied revision, None if there is no stored revision
+    #[serde(rename = "inRevision", skip_serializing_if = "Option::is_none")]
+    pub in_revision: Option<bool>,
+}
+
+#[derive(Serialize)]
+pub struct DeploymentStatus {
+    pub revision: Option<Revision>,
+    pub objects: Vec<ObjectStatus>,
+}
+
+fn last_manager(value: &Value) -> Option<String> {
+    value["metadata"]["managedFields"]
+        .as_array()?
+        .iter()
+        .max_by(|a, b| a["time"].as_str().cmp(&b["time"].as_str()))
+        .and_then(|field| field["manager"].as_str())
+        .map(ToOwned::to_owned)
+}
+
+/// Collect status of every deployed object
+pub async fn status(
+    client: Client,
+    discovery: &Discovery,
+    namespace: &str,
+    label: (&str, &str),
+) -> Result<DeploymentStatus> {
+    let types = discovery.types();
+    let revision = load_revision(client.clone(), namespace, label).await?;
+
+    let mut objects = Vec::new();
+    for object in find_deployed(client.clone(), discovery, label, &default_excluded_kinds()).await?
+    {
+        let value = match get(client.clone(), &make_url("", &object, &types)).await? {
+            Some(v) => v,
+            // Removed since listing
+            None => continue,
+        };
+
+        objects.push(ObjectStatus {
+            created: value["metadata"]["creationTimestamp"]
+                .as_str()
+                .map(ToOwned::to_owned),
+            manager: last_manager(&value),
+            health: health(&object.kind, &value),
+            in_revision: revision
+                .as_ref()
+                .map(|revision| revision.objects.contains(&object)),
+            object,
+        });
+    }
+
+    Ok(DeploymentStatus { revision, objects })
+}
@@ -0,0 +1,82 @@
+use fieldpath::{path, FieldpathExt};
+use serde_json::Value;
+
+/// Remove fields, which are maintained by apiserver, and don't describe object itself
+pub fn strip_server_fields(value: &mut Value) {
+    for path in [
+        path!(."metadata"."managedFields"),
+        path!(."metadata"."selfLink"),
+        path!(."metadata"."uid"),
+        path!(."metadata"."resourceVersion"),
+        path!(."metadata"."generation"),
+        path!(."metadata"."creationTimestamp"),
+        path!(."metadata"."annotations"."kubectl.kubernetes.io/last-applied-configuration"),
+        path!(."status"),
+    ]
+    .iter()
+    {
+        let _res = value.remove_path(path);
+    }
+}
+
+/// Fields, owned by apply of manager
+fn applied_fields<'a>(value: &'a Value, manager: &str) -> Option<&'a Value> {
+    value["metadata"]["managedFields"]
+        .as_array()?
+        .iter()
+        .find(|entry| entry["manager"] == manager && entry["operation"] == "Apply")
+        .map(|entry| &entry["fieldsV1"])
+}
+
+/// Would apply change anything, judging by its dry-run result
+///
+/// Ownership is compared too, otherwise fields, which have the same value but are owned
+/// by someone else, would be left unowned and never pruned
+pub fn is_unchanged(live: &Value, dry_run: &Value, manager: &str) -> bool {
+    if applied_fields(live, manager) != applied_fields(dry_run, manager) {
+        return false;
+    }
+    let mut live = live.clone();
+    let mut dry_run = dry_run.clone();
+    strip_server_fields(&mut live);
+    strip_server_fields(&mut dry_run);
+    live == dry_run
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn object(data: &str, resource_version: &str, fields: Value) -> Value {
+        json!({
+            "apiVersion": "v1",
+            "kind": "ConfigMap",
+            "metadata": {
+                "name": "app",
+                "resourceVersion": resource_version,
+                "managedFields": [
+                    {"manager": "hayasaka", "operation": "Apply", "time": resource_version, "fieldsV1": fields},
+                ],
+            },
+            "data": {"key": data},
+        })
+    }
+
+    #[test]
+    fn comparison() {
+        let fields = json!({"f:data": {"f:key": {}}});
+        let live = object("a", "1", fields.clone());
+        assert!(is_unchanged(
+            &live,
+            &object("a", "2", fields.clone()),
+            "hayasaka"
+        ));
+        assert!(!is_unchanged(&live, &object("b", "2", fields), "hayasaka"));
+        assert!(!is_unchanged(
+            &live,
+            &object("a", "2", json!({})),
+            "hayasaka"
+        ));
+    }
+}
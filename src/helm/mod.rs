@@ -1,7 +1,10 @@
 mod template;
 
-use crate::bail;
-use fieldpath::FieldpathExt;
+use crate::{
+    apply::{format_diff, redacted},
+    bail,
+    progress::{self, Stage},
+};
 use jrsonnet_evaluator::{
     error::Result, native::NativeCallback, unwrap_type, Context, FuncVal, LazyBinding, LazyVal,
     ObjMember, ObjValue, Val,
@@ -10,7 +13,6 @@ use jrsonnet_parser::{Param, ParamsDesc, Visibility};
 use jrsonnet_types::ty;
 use rustc_hash::FxHashMap;
 use serde_json::Value;
-use std::fmt::Write;
 use std::{convert::TryInto, hash::BuildHasherDefault, path::PathBuf, rc::Rc, thread};
 use template::template_helm;
 
@@ -63,22 +65,39 @@ pub fn generate_key(val: &ObjValue) -> Result<String> {
 
 fn helm_to_map(values: Vec<Value>, purifier: Rc<FuncVal>) -> Result<Val> {
     let mut out = FxHashMap::with_capacity_and_hasher(values.len(), BuildHasherDefault::default());
+    // Helm output item index and key before purification, for every produced key
+    let mut seen = FxHashMap::<String, (usize, String, Value)>::default();
 
-    for value in values {
+    for (idx, value) in values.into_iter().enumerate() {
         if matches!(&value, Value::Null) {
             continue;
         }
         let val: Val = (&value).into();
         let obj = unwrap_type!(|| "helm output item".into(), val, ty!(object) => Val::Obj);
         let old_name = generate_key(&obj)?;
-        let new_val = purifier
-            .evaluate_values(Context::new(), &[Val::Str(old_name.into()), Val::Obj(obj)])?;
+        let new_val = purifier.evaluate_values(
+            Context::new(),
+            &[Val::Str(old_name.clone().into()), Val::Obj(obj)],
+        )?;
 
         match new_val {
             Val::Null => continue,
             val => {
                 let obj = unwrap_type!(|| "purifier output".into(), val, ty!(object) => Val::Obj);
                 let new_name = generate_key(&obj)?;
+                let new_value: Value = (&Val::Obj(obj.clone())).try_into()?;
+                if let Some((first_idx, first_name, first_value)) = seen.get(&new_name) {
+                    bail!(
+                        "helm output contains {} twice, as item #{} ({}) and item #{} ({}):{}",
+                        new_name,
+                        first_idx,
+                        first_name,
+                        idx,
+                        old_name,
+                        format_diff(&redacted(first_value), &redacted(&new_value)),
+                    );
+                }
+                seen.insert(new_name.clone(), (idx, old_name, new_value));
                 out.insert(
                     new_name.into(),
                     ObjMember {
@@ -111,6 +130,7 @@ fn helm_template(
     let purifier =
         unwrap_type!(|| "purifier".to_owned(), args[3].clone(), ty!(function) => Val::Func);
 
+    progress::stage(Stage::HelmRender, 0);
     // Spawn another thread, because helm is slow
     let helm_a = if check_purity {
         let name_a = name.to_owned().to_string();
@@ -137,34 +157,21 @@ fn helm_template(
     } else {
         None
     };
+    progress::stage(Stage::Evaluate, 0);
 
     if let Some(helmval_a) = helmval_a {
-        let json_patch = json_patch::diff(&helmval_a, &helmval_b);
-        if !json_patch.0.is_empty() {
-            let mut out = "impurity found between two helm runs:".to_owned();
-            for p in json_patch.0 {
-                match p {
-                    json_patch::PatchOperation::Add(a) => {
-                        write!(out, "\n+ {}", fieldpath::PathBuf::from_rfc6901(a.path))
-                    }
-                    json_patch::PatchOperation::Remove(r) => {
-                        write!(out, "\n- {}", fieldpath::PathBuf::from_rfc6901(r.path))
-                    }
-                    json_patch::PatchOperation::Replace(r) => {
-                        let path = fieldpath::PathBuf::from_rfc6901(r.path);
-                        write!(
-                            out,
-                            "\nr {}\n  - {}\n  + {}",
-                            path,
-                            helmval_a.get_path(&path).unwrap(),
-                            helmval_b.get_path(&path).unwrap()
-                        )
-                    }
-                    _ => unreachable!(),
-                }
+        // Values are maps of objects by name
+        let redact_items = |value: &Value| -> Value {
+            value
+                .as_object()
                 .unwrap()
-            }
-            crate::bail!("{}", out)
+                .iter()
+                .map(|(name, object)| (name.clone(), redacted(object)))
+                .collect()
+        };
+        let diff = format_diff(&redact_items(&helmval_a), &redact_items(&helmval_b));
+        if !diff.is_empty() {
+            crate::bail!("impurity found between two helm runs:{}", diff)
         }
     }
 
@@ -1,7 +1,9 @@
 mod apply;
 mod helm;
+mod progress;
+mod source;
 
-use chrono::{SecondsFormat, Utc};
+use chrono::{DateTime, SecondsFormat, Utc};
 use clap::Clap;
 use helm::create_helm_template;
 use jrsonnet_cli::{ConfigureState, GeneralOpts, InputOpts};
@@ -11,10 +13,13 @@ use jrsonnet_interner::IStr;
 use kube::Config;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use source::{OutputPath, Step};
 use std::{
     convert::{TryFrom, TryInto},
+    io::Write,
     path::PathBuf,
     rc::Rc,
+    time::Duration,
 };
 use tokio::runtime::Builder;
 
@@ -53,11 +58,59 @@ struct DeployOpts {
     /// Ignore changes applied by specified controllers
     #[clap(long)]
     ignore_changes_by: Vec<String>,
+    /// Apply only objects matching selector, i.e `kind=ConfigMap,name=app-*,label:tier=web`
+    /// Prune is limited to kinds of selected objects
+    #[clap(long)]
+    only: Vec<apply::Selector>,
+    /// Skip objects matching selector
+    #[clap(long)]
+    exclude: Vec<apply::Selector>,
+    /// Wait for deployment lock held by another run up to specified amount of seconds,
+    /// by default deploy fails immediately
+    #[clap(long)]
+    lock_timeout: Option<u64>,
+    /// Take over deployment lock, even if it is held by another run
+    #[clap(long)]
+    force_unlock: bool,
+    /// Create missing deployment namespace, and namespaces used by objects
+    /// Created namespaces are removed by destroy. Without this option deployment namespace
+    /// should already exist, as deployment lock is stored in it
+    #[clap(long)]
+    create_namespaces: bool,
+    /// How long to wait for Jobs and Pods of pre and post phases in seconds, 600 by default
+    #[clap(long)]
+    task_timeout: Option<u64>,
+    /// Never prune objects of this kind, i.e `cert-manager.io/CertificateRequest`,
+    /// in addition to Endpoints and EndpointSlices
+    #[clap(long)]
+    exclude_kind: Vec<apply::GroupKind>,
+    /// Fail, instead of skipping kinds, which can't be listed for pruning
+    #[clap(long)]
+    strict_access: bool,
+    /// Write json report of deployment to file
+    #[clap(long)]
+    report: Option<PathBuf>,
+    /// Print json report of deployment to stdout
+    #[clap(long, possible_values = &["json"])]
+    output: Option<String>,
+    /// Print plain log lines instead of live progress, which is shown on terminal by default
+    #[clap(long)]
+    no_progress: bool,
+    /// Hide value of field in logs, diffs and reports, i.e `.spec.token`
+    /// Data of Secrets is always hidden
+    #[clap(long)]
+    redact: Vec<fieldpath::PathBuf>,
+    /// Post json describing deployment start, finish and pruning to url
+    #[clap(long)]
+    notify_webhook: Vec<String>,
+    /// Run shell command on deployment start, finish and pruning,
+    /// event is passed as json on stdin, and its kind in HAYASAKA_EVENT variable
+    #[clap(long)]
+    notify_command: Vec<String>,
 }
 
 #[derive(Clap)]
-#[clap(version = "0.1.0", author = "Lach")]
-struct Opts {
+struct DeployCommand {
     #[clap(flatten)]
     deploy: DeployOpts,
     #[clap(flatten)]
@@ -66,15 +119,122 @@ struct Opts {
     input: InputOpts,
 }
 
-fn flatten(val: Val, out: &mut Vec<Val>) -> Result<()> {
+#[derive(Clap)]
+struct DestroyOpts {
+    /// Name of deployment to remove
+    name: String,
+    /// Do not ask for confirmation
+    #[clap(long)]
+    yes: bool,
+    /// How long to wait for objects to be removed, in seconds
+    #[clap(long, default_value = "300")]
+    timeout: u64,
+    /// Never remove objects of this kind, i.e `cert-manager.io/CertificateRequest`,
+    /// in addition to Endpoints and EndpointSlices
+    #[clap(long)]
+    exclude_kind: Vec<apply::GroupKind>,
+}
+
+#[derive(Clap)]
+struct StatusOpts {
+    /// Name of deployment to inspect
+    name: String,
+    /// Output format
+    #[clap(long, short, default_value = "table", possible_values = &["table", "json"])]
+    output: String,
+}
+
+#[derive(Clap)]
+struct MigrateManagersOpts {
+    /// Name of deployment to migrate
+    name: String,
+    /// Managers, whose fields should be transferred to hayasaka,
+    /// previous hayasaka manager is always included
+    #[clap(long)]
+    from: Vec<String>,
+    /// Only show objects, which would be migrated, and their fields, which would be removed
+    #[clap(long)]
+    dry_run: bool,
+}
+
+#[derive(Clap)]
+struct RbacOpts {
+    /// Name of deployment
+    name: String,
+    /// ServiceAccount, which will deploy, i.e `ci:deployer`
+    #[clap(long)]
+    service_account: String,
+    /// Output format, yaml by default, jsonnet is printed as json, which is valid jsonnet
+    #[clap(long, short, possible_values = &["yaml", "jsonnet"])]
+    output: Option<String>,
+}
+
+#[derive(Clap)]
+struct RbacCommand {
+    #[clap(flatten)]
+    rbac: RbacOpts,
+    #[clap(flatten)]
+    jsonnet: GeneralOpts,
+    #[clap(flatten)]
+    input: InputOpts,
+}
+
+#[derive(Clap)]
+enum SubCommand {
+    /// Evaluate deployment, and apply it to cluster
+    ///
+    /// Exit codes: 1 - setup failed (config, discovery, lock), 3 - evaluation failed,
+    /// 4 - apply failed, 5 - task of pre or post phase failed or timed out
+    Deploy(DeployCommand),
+    /// Remove every object of deployment from cluster
+    Destroy(DestroyOpts),
+    /// Show objects of deployment, and their health
+    Status(StatusOpts),
+    /// Transfer ownership of fields from other managers to hayasaka
+    MigrateManagers(MigrateManagersOpts),
+    /// Evaluate deployment, and print minimal roles needed to deploy it
+    Rbac(RbacCommand),
+}
+
+#[derive(Clap)]
+#[clap(version = "0.1.0", author = "Lach")]
+struct Opts {
+    /// Ignore cached list of cluster resources
+    #[clap(long, global = true)]
+    refresh_discovery: bool,
+    #[clap(subcommand)]
+    command: SubCommand,
+}
+
+/// Whether object is a list of objects, i.e `v1 List`, or `apps/v1 DeploymentList` with
+/// only Deployments in items, given kinds of its items
+///
+/// Other kinds, which just end with `List`, i.e `AllowList` custom resource, are kept as is
+fn is_list(api_version: &str, kind: &str, item_kinds: &[Option<String>]) -> bool {
+    if api_version == "v1" && kind == "List" {
+        return true;
+    }
+    match kind.strip_suffix("List") {
+        Some(item_kind) if !item_kind.is_empty() => {
+            !item_kinds.is_empty() && item_kinds.iter().all(|k| k.as_deref() == Some(item_kind))
+        }
+        _ => false,
+    }
+}
+
+/// Collect objects, with jsonnet paths they are located at
+///
+/// Lists are expanded, nulls are skipped
+fn flatten(val: Val, path: &OutputPath, out: &mut Vec<(OutputPath, Val)>) -> Result<()> {
     match val {
         Val::Arr(a) => {
             for (idx, item) in a.iter().enumerate() {
+                let path = path.join(Step::Index(idx));
                 jrsonnet_evaluator::push_stack_frame(
                     None,
                     || format!("[{}]", idx),
                     || {
-                        flatten(item?, out)?;
+                        flatten(item?, &path, out)?;
                         Ok(())
                     },
                 )?;
@@ -85,22 +245,67 @@ fn flatten(val: Val, out: &mut Vec<Val>) -> Result<()> {
             if vis.get(&IStr::from("kind")).is_some()
                 && vis.get(&IStr::from("apiVersion")).is_some()
             {
-                out.push(Val::Obj(obj));
+                let string = |field: &str| -> Result<Option<String>> {
+                    Ok(match obj.get(field.into())? {
+                        Some(Val::Str(s)) => Some(s.to_string()),
+                        _ => None,
+                    })
+                };
+                let items = match obj.get("items".into())? {
+                    Some(Val::Arr(items)) => Some(items),
+                    _ => None,
+                };
+                let item_kinds = match &items {
+                    Some(items) => {
+                        let mut kinds = Vec::new();
+                        for item in items.iter() {
+                            kinds.push(match item? {
+                                Val::Obj(item) => match item.get("kind".into())? {
+                                    Some(Val::Str(kind)) => Some(Some(kind.to_string())),
+                                    _ => Some(None),
+                                },
+                                _ => None,
+                            });
+                        }
+                        kinds.into_iter().collect::<Option<Vec<_>>>()
+                    }
+                    None => None,
+                };
+                let is_list = match (string("apiVersion")?, string("kind")?, item_kinds) {
+                    (Some(api_version), Some(kind), Some(item_kinds)) => {
+                        is_list(&api_version, &kind, &item_kinds)
+                    }
+                    _ => false,
+                };
+                if is_list {
+                    // i.e `v1 List`, as returned by `kubectl get -o json`
+                    let path = path.join(Step::Field("items".to_owned()));
+                    jrsonnet_evaluator::push_stack_frame(
+                        None,
+                        || ".items".to_owned(),
+                        || flatten(Val::Arr(items.unwrap()), &path, out),
+                    )?;
+                } else {
+                    out.push((path.clone(), Val::Obj(obj)));
+                }
             } else {
                 for field in vis {
+                    let path = path.join(Step::Field(field.0.to_string()));
                     jrsonnet_evaluator::push_stack_frame(
                         None,
-                        || format!(".{}", field.0),
+                        || source::field_accessor(&field.0),
                         || {
-                            flatten(obj.get(field.0.clone())?.unwrap(), out)?;
+                            flatten(obj.get(field.0.clone())?.unwrap(), &path, out)?;
                             Ok(())
                         },
                     )?;
                 }
             }
         }
+        // Allows `if cond then obj` idiom
+        Val::Null => {}
         _ => bail!(
-            "top level objects should be either arrays or objects, got {}",
+            "top level objects should be either arrays, objects or nulls, got {}",
             val.value_type(),
         ),
     }
@@ -112,19 +317,37 @@ fn main_template(
     evaluator: EvaluationState,
     opts: &GeneralOpts,
     input: &InputOpts,
-) -> Result<Vec<Value>> {
+) -> Result<Vec<(String, Value)>> {
     opts.configure(&evaluator).unwrap();
 
-    let value = evaluator.evaluate_file_raw(&PathBuf::from(&input.input))?;
+    let file = PathBuf::from(&input.input);
+    let value = evaluator.evaluate_file_raw(&file)?;
     let value = evaluator.with_tla(value)?;
     let mut out = Vec::new();
 
-    evaluator.run_in_state(|| flatten(value, &mut out))?;
+    evaluator.run_in_state(|| flatten(value, &OutputPath::default(), &mut out))?;
 
+    // Evaluated members don't keep their location, so it is found in syntax tree of input file
+    let text = std::fs::read_to_string(&file).unwrap_or_default();
+    let tree = jrsonnet_parser::parse(
+        &text,
+        &jrsonnet_parser::ParserSettings {
+            loc_data: true,
+            file_name: Rc::new(file.clone()),
+        },
+    )
+    .ok();
     let mut json_out = Vec::new();
     evaluator.run_in_state(|| {
-        for value in out {
-            json_out.push((&value).try_into()?);
+        for (path, value) in out {
+            let line = tree
+                .as_ref()
+                .and_then(|tree| source::find_line(&text, tree, &path));
+            let source = match line {
+                Some(line) => format!("{}:{} {}", file.display(), line, path),
+                None => path.to_string(),
+            };
+            json_out.push((source, (&value).try_into()?));
         }
         Ok(()) as jrsonnet_evaluator::error::Result<()>
     })?;
@@ -138,21 +361,14 @@ struct UnstructuredMetadata {
     namespace: Option<String>,
 }
 
-async fn main_real() -> Result<()> {
-    if std::env::var_os("RUST_LOG").is_none() {
-        std::env::set_var("RUST_LOG", "info");
-    }
-
-    env_logger::init();
-    let opts: Opts = Opts::parse();
-
-    let mut config = Config::infer()
-        .await
-        .map_err(|e| anyhow!("failed to load config: {}", e))?;
-    config.default_ns = opts.deploy.name.clone();
-    let client =
-        kube::Client::try_from(config).map_err(|e| anyhow!("failed to construct client: {}", e))?;
-
+/// Evaluate deployment, error is returned formatted with stack trace
+///
+/// Every object is returned with its source, i.e `main.jsonnet:12 .app.deployment`
+fn evaluate(
+    name: &str,
+    jsonnet: &GeneralOpts,
+    input: &InputOpts,
+) -> std::result::Result<Vec<(String, Value)>, String> {
     let es = EvaluationState::default();
     es.with_stdlib();
     let deployment_obj = ObjValue::new_empty()
@@ -161,9 +377,7 @@ async fn main_real() -> Result<()> {
             ObjMember {
                 add: false,
                 visibility: jrsonnet_parser::Visibility::Normal,
-                invoke: LazyBinding::Bound(LazyVal::new_resolved(Val::Str(
-                    opts.deploy.name.clone().into(),
-                ))),
+                invoke: LazyBinding::Bound(LazyVal::new_resolved(Val::Str(name.to_owned().into()))),
                 location: None,
             },
         )
@@ -198,38 +412,452 @@ async fn main_real() -> Result<()> {
 
     es.add_native(
         "kubers.helmTemplate".into(),
-        Rc::new(create_helm_template(opts.deploy.name.clone().into())),
+        Rc::new(create_helm_template(name.to_owned().into())),
     );
 
-    let kubers_obj = es.evaluate_snippet_raw(
-        Rc::new(PathBuf::from("kubers prelude")),
-        include_str!("kubersApi.jsonnet").into(),
-    )?;
+    let kubers_obj = es
+        .evaluate_snippet_raw(
+            Rc::new(PathBuf::from("kubers prelude")),
+            include_str!("kubersApi.jsonnet").into(),
+        )
+        .map_err(|e| es.stringify_err(&e))?;
     es.settings_mut()
         .globals
         .insert("hayasaka".into(), kubers_obj);
-    let templated = match es.run_in_state(|| main_template(es.clone(), &opts.jsonnet, &opts.input))
+    es.run_in_state(|| main_template(es.clone(), jsonnet, input))
+        .map_err(|e| es.stringify_err(&e))
+}
+
+/// Evaluate deployment, exits on evaluation error
+fn render(name: &str, jsonnet: &GeneralOpts, input: &InputOpts) -> Vec<(String, Value)> {
+    match evaluate(name, jsonnet, input) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(apply::Outcome::EvaluationFailed.exit_code());
+        }
+    }
+}
+
+const LABEL: &str = "hayasaka.delta.rocks";
+
+async fn create_client(
+    name: &str,
+    refresh_discovery: bool,
+) -> Result<(kube::Client, apply::Discovery)> {
+    let mut config = Config::infer()
+        .await
+        .map_err(|e| anyhow!("failed to load config: {}", e))?;
+    config.default_ns = name.to_owned();
+    let cache = std::env::var_os("HOME")
+        .map(|home| apply::discovery_cache_path(home.as_ref(), config.cluster_url.as_str()));
+    let client =
+        kube::Client::try_from(config).map_err(|e| anyhow!("failed to construct client: {}", e))?;
+    let discovery = apply::Discovery::load(client.clone(), cache, refresh_discovery)
+        .await
+        .map_err(|e| anyhow!("failed to discover cluster resources: {}", e))?;
+    Ok((client, discovery))
+}
+
+fn confirm(question: &str) -> Result<bool> {
+    eprint!("{} [y/N] ", question);
+    std::io::stderr()
+        .flush()
+        .map_err(|e| anyhow!("failed to flush stderr: {}", e))?;
+    let mut answer = String::new();
+    std::io::stdin()
+        .read_line(&mut answer)
+        .map_err(|e| anyhow!("failed to read answer: {}", e))?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+async fn main_destroy(opts: DestroyOpts, refresh_discovery: bool) -> Result<()> {
+    let (client, discovery) = create_client(&opts.name, refresh_discovery).await?;
+
+    let mut excluded_kinds = apply::default_excluded_kinds();
+    excluded_kinds.extend(opts.exclude_kind);
+    let found = match async {
+        let mut found = apply::find_deployed(
+            client.clone(),
+            &discovery,
+            (LABEL, &opts.name),
+            &excluded_kinds,
+        )
+        .await?;
+        found.extend(apply::find_created_namespaces(client.clone(), (LABEL, &opts.name)).await?);
+        Ok::<_, apply::Error>(found)
+    }
+    .await
+    .map_err(anyhow::Error::from)
     {
         Ok(v) => v,
         Err(e) => {
-            eprintln!("{}", es.stringify_err(&e));
+            eprintln!("{}", e);
             std::process::exit(1);
         }
     };
+    if found.is_empty() {
+        log::info!("nothing to remove");
+    } else {
+        println!("Deployment {} consists of:", opts.name);
+        for item in found.iter() {
+            println!("- {}", item);
+        }
+        if !opts.yes {
+            match confirm(&format!("Remove {} objects?", found.len())) {
+                Ok(true) => {}
+                Ok(false) => {
+                    eprintln!("cancelled");
+                    std::process::exit(1);
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    match async {
+        apply::destroy_multi(
+            client.clone(),
+            &discovery,
+            found,
+            Duration::from_secs(opts.timeout),
+        )
+        .await?;
+        apply::remove_revision(client, &opts.name, (LABEL, &opts.name)).await
+    }
+    .await
+    .map_err(anyhow::Error::from)
+    {
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+
+    Ok(())
+}
 
-    let legacy_manager = format!("hayasaka.lach.pw/{}", opts.deploy.name);
-    match apply::apply_multi(
+fn format_age(created: &str) -> String {
+    let created = match DateTime::parse_from_rfc3339(created) {
+        Ok(v) => v.with_timezone(&Utc),
+        Err(_) => return "?".to_owned(),
+    };
+    let seconds = (Utc::now() - created).num_seconds().max(0);
+    match seconds {
+        s if s < 60 => format!("{}s", s),
+        s if s < 60 * 60 => format!("{}m", s / 60),
+        s if s < 60 * 60 * 24 => format!("{}h", s / 60 / 60),
+        s => format!("{}d", s / 60 / 60 / 24),
+    }
+}
+
+fn print_status_table(status: &apply::DeploymentStatus) {
+    if let Some(revision) = &status.revision {
+        println!(
+            "Revision {}, deployed at {}",
+            revision.number, revision.deployed_at
+        );
+    } else {
+        println!("No revision recorded");
+    }
+
+    let mut rows = vec![[
+        "KIND".to_owned(),
+        "NAMESPACE".to_owned(),
+        "NAME".to_owned(),
+        "AGE".to_owned(),
+        "MANAGER".to_owned(),
+        "HEALTH".to_owned(),
+    ]];
+    let mut stale = false;
+    for item in status.objects.iter() {
+        let mark = if item.in_revision == Some(false) {
+            stale = true;
+            "*"
+        } else {
+            ""
+        };
+        rows.push([
+            format!("{}{}", mark, item.object.kind),
+            item.object.metadata.namespace.clone().unwrap_or_default(),
+            item.object.metadata.name.clone(),
+            item.created.as_deref().map(format_age).unwrap_or_default(),
+            item.manager.clone().unwrap_or_default(),
+            item.health.to_string(),
+        ]);
+    }
+
+    let mut widths = [0; 6];
+    for row in rows.iter() {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    for row in rows.iter() {
+        let line = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    }
+    if stale {
+        println!("\n* - object is not part of last applied revision");
+    }
+}
+
+async fn main_status(opts: StatusOpts, refresh_discovery: bool) -> Result<()> {
+    let (client, discovery) = create_client(&opts.name, refresh_discovery).await?;
+
+    let status = match apply::status(client, &discovery, &opts.name, (LABEL, &opts.name))
+        .await
+        .map_err(anyhow::Error::from)
+    {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if opts.output == "json" {
+        println!("{}", serde_json::to_string_pretty(&status).unwrap());
+    } else {
+        print_status_table(&status);
+    }
+
+    Ok(())
+}
+
+fn legacy_manager(name: &str) -> String {
+    format!("hayasaka.lach.pw/{}", name)
+}
+
+async fn main_migrate_managers(opts: MigrateManagersOpts, refresh_discovery: bool) -> Result<()> {
+    let (client, discovery) = create_client(&opts.name, refresh_discovery).await?;
+
+    let mut from = opts.from;
+    from.push(legacy_manager(&opts.name));
+
+    match apply::migrate_managers(
+        client,
+        &discovery,
+        (LABEL, &opts.name),
+        &from,
+        &format!("{}/{}", LABEL, opts.name),
+        opts.dry_run,
+    )
+    .await
+    .map_err(anyhow::Error::from)
+    {
+        Ok(migrated) => log::info!("migrated {} objects", migrated),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+
+    Ok(())
+}
+
+async fn main_rbac(opts: RbacCommand, refresh_discovery: bool) -> Result<()> {
+    let name = &opts.rbac.name;
+    let service_account = &opts.rbac.service_account;
+    let subject = match service_account.find(':') {
+        Some(idx) => (&service_account[..idx], &service_account[idx + 1..]),
+        None => bail!(
+            "expected service account as namespace:name, got {}",
+            service_account
+        ),
+    };
+    let (client, mut discovery) = create_client(name, refresh_discovery).await?;
+    let mut templated = render(name, &opts.jsonnet, &opts.input)
+        .into_iter()
+        .map(|(_, value)| value)
+        .collect::<Vec<_>>();
+
+    // Roles should match objects, as they are going to be applied
+    let rbac = match async {
+        apply::upgrade_target(client, &mut discovery, &mut templated).await?;
+        apply::generate_rbac(&discovery, name, (LABEL, name), &templated, subject)
+    }
+    .await
+    .map_err(anyhow::Error::from)
+    {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if opts.rbac.output.as_deref() == Some("jsonnet") {
+        println!("{}", serde_json::to_string_pretty(&rbac).unwrap());
+    } else {
+        for item in rbac {
+            print!("{}", serde_yaml_with_quirks::to_string(&item).unwrap());
+            println!();
+        }
+    }
+
+    Ok(())
+}
+
+/// Output report, and exit with code of outcome, if deployment has failed
+async fn finish_deploy(
+    name: &str,
+    events: &apply::Sinks,
+    report_path: Option<&PathBuf>,
+    output: Option<&str>,
+    mut report: apply::Report,
+    outcome: apply::Outcome,
+) -> Result<()> {
+    progress::finish();
+    report.finish(outcome);
+    events.emit(&apply::Event::finished(name, &report)).await;
+    if let Some(path) = report_path {
+        let written = std::fs::File::create(path)
+            .map_err(anyhow::Error::from)
+            .and_then(|file| Ok(serde_json::to_writer_pretty(file, &report)?));
+        if let Err(e) = written {
+            log::warn!("failed to write report to {}: {}", path.display(), e);
+        }
+    }
+    if output == Some("json") {
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    }
+    if outcome != apply::Outcome::Succeeded {
+        std::process::exit(outcome.exit_code());
+    }
+    Ok(())
+}
+
+async fn main_deploy(opts: DeployCommand, refresh_discovery: bool) -> Result<()> {
+    apply::set_redacted_paths(opts.deploy.redact.clone());
+    progress::init(
+        !opts.deploy.no_progress
+            && atty::is(atty::Stream::Stderr)
+            && std::env::var_os("CI").is_none(),
+    );
+    let name = opts.deploy.name.clone();
+    let webhooks = opts.deploy.notify_webhook.iter().cloned();
+    let commands = opts.deploy.notify_command.iter().cloned();
+    let events = apply::Sinks(
+        webhooks
+            .map(apply::Sink::Webhook)
+            .chain(commands.map(apply::Sink::Command))
+            .collect(),
+    );
+    let report_path = opts.deploy.report.clone();
+    let output = opts.deploy.output.clone();
+    let finish = |report, outcome| {
+        finish_deploy(
+            &name,
+            &events,
+            report_path.as_ref(),
+            output.as_deref(),
+            report,
+            outcome,
+        )
+    };
+
+    events
+        .emit(&apply::Event::new(apply::EventKind::Started, &name))
+        .await;
+    let mut report = apply::Report::default();
+    progress::stage(progress::Stage::Discovery, 0);
+    let (client, mut discovery) = match create_client(&opts.deploy.name, refresh_discovery).await {
+        Ok(v) => v,
+        Err(e) => {
+            progress::finish();
+            eprintln!("{}", e);
+            report.errors.push(e.to_string());
+            return finish(report, apply::Outcome::SetupFailed).await;
+        }
+    };
+    progress::stage(progress::Stage::Evaluate, 0);
+    let (sources, templated): (Vec<_>, Vec<_>) =
+        match evaluate(&opts.deploy.name, &opts.jsonnet, &opts.input) {
+            Ok(v) => v.into_iter().unzip(),
+            Err(e) => {
+                progress::finish();
+                eprintln!("{}", e);
+                report.errors.push(e);
+                return finish(report, apply::Outcome::EvaluationFailed).await;
+            }
+        };
+
+    let legacy_manager = legacy_manager(&opts.deploy.name);
+    let mut options = apply::ApplyOptions {
+        prune: true,
+        selection: apply::Selection {
+            only: opts.deploy.only,
+            exclude: opts.deploy.exclude,
+        },
+        task_timeout: Duration::from_secs(opts.deploy.task_timeout.unwrap_or(600)),
+        excluded_kinds: apply::default_excluded_kinds()
+            .into_iter()
+            .chain(opts.deploy.exclude_kind)
+            .collect(),
+        strict_access: opts.deploy.strict_access,
+        create_namespaces: opts.deploy.create_namespaces,
+        sources,
+        events: events.clone(),
+        lock: None,
+    };
+    let ignore_changes_by = &opts.deploy.ignore_changes_by;
+
+    let lock = match async {
+        if opts.deploy.create_namespaces {
+            // Lock lease can't be created in missing namespace
+            apply::create_namespace(
+                client.clone(),
+                &opts.deploy.name,
+                (LABEL, &opts.deploy.name),
+            )
+            .await?;
+        }
+        apply::DeploymentLock::acquire(
+            client.clone(),
+            &opts.deploy.name,
+            (LABEL, &opts.deploy.name),
+            &apply::default_holder(),
+            Duration::from_secs(opts.deploy.lock_timeout.unwrap_or(0)),
+            opts.deploy.force_unlock,
+        )
+        .await
+    }
+    .await
+    .map_err(anyhow::Error::from)
+    {
+        Ok(v) => v,
+        Err(e) => {
+            progress::finish();
+            eprintln!("{}", e);
+            report.errors.push(e.to_string());
+            return finish(report, apply::Outcome::SetupFailed).await;
+        }
+    };
+    options.lock = Some(lock.state());
+
+    let result = apply::apply_multi(
         client,
+        &mut discovery,
         &opts.deploy.name,
-        &format!("hayasaka.delta.rocks/{}", opts.deploy.name),
-        ("hayasaka.delta.rocks", &opts.deploy.name),
+        &format!("{}/{}", LABEL, opts.deploy.name),
+        (LABEL, &opts.deploy.name),
         templated,
         |obj, manager, path| {
             if manager == legacy_manager {
                 log::warn!("upgrading hayasaka version in {}", obj);
                 return apply::ResolutionStrategy::Force;
             }
-            if manager == "k3s" || opts.deploy.ignore_changes_by.contains(&manager.to_owned()) {
+            if manager == "k3s" || ignore_changes_by.contains(&manager.to_owned()) {
                 log::warn!(
                     "using changes at {} in {} (made by {})",
                     fieldpath::PathBuf(path.to_owned()),
@@ -245,19 +873,43 @@ async fn main_real() -> Result<()> {
                 fieldpath::PathBuf(path.to_owned())
             ))
         },
-        true,
+        &options,
+        &mut report,
     )
-    .await
-    .map_err(anyhow::Error::from)
-    {
-        Ok(_) => {}
+    .await;
+    if let Err(e) = lock.release().await {
+        log::warn!("failed to release deployment lock: {}", e);
+    }
+
+    match result {
+        Ok(_) => finish(report, apply::Outcome::Succeeded).await,
         Err(e) => {
+            progress::finish();
             eprintln!("{}", e);
-            std::process::exit(1);
+            report.errors.push(e.to_string());
+            finish(report, apply::Outcome::of(&e)).await
         }
     }
+}
 
-    Ok(())
+async fn main_real() -> Result<()> {
+    if std::env::var_os("RUST_LOG").is_none() {
+        std::env::set_var("RUST_LOG", "info");
+    }
+
+    let logger = env_logger::Builder::from_default_env().build();
+    log::set_max_level(logger.filter());
+    log::set_boxed_logger(Box::new(progress::Logger(logger))).unwrap();
+    let opts: Opts = Opts::parse();
+
+    let refresh_discovery = opts.refresh_discovery;
+    match opts.command {
+        SubCommand::Deploy(opts) => main_deploy(opts, refresh_discovery).await,
+        SubCommand::Destroy(opts) => main_destroy(opts, refresh_discovery).await,
+        SubCommand::Status(opts) => main_status(opts, refresh_discovery).await,
+        SubCommand::MigrateManagers(opts) => main_migrate_managers(opts, refresh_discovery).await,
+        SubCommand::Rbac(opts) => main_rbac(opts, refresh_discovery).await,
+    }
 }
 
 fn main_tokio() {
@@ -266,7 +918,11 @@ fn main_tokio() {
         .enable_io()
         .build()
         .unwrap()
-        .block_on(main_real())
+        .block_on(async {
+            let result = main_real().await;
+            progress::finish();
+            result
+        })
         .unwrap();
 }
 
@@ -278,3 +934,29 @@ fn main() {
         .join()
         .unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists() {
+        let kinds = |kinds: &[&str]| {
+            kinds
+                .iter()
+                .map(|k| Some(k.to_string()))
+                .collect::<Vec<_>>()
+        };
+        assert!(is_list("v1", "List", &kinds(&["Service", "Deployment"])));
+        assert!(is_list("v1", "List", &[]));
+        assert!(is_list(
+            "apps/v1",
+            "DeploymentList",
+            &kinds(&["Deployment"])
+        ));
+        assert!(!is_list("apps/v1", "DeploymentList", &kinds(&["Service"])));
+        assert!(!is_list("example.com/v1", "AllowList", &[]));
+        assert!(!is_list("example.com/v1", "AllowList", &[None, None]));
+        assert!(!is_list("example.com/v1", "List", &kinds(&["Service"])));
+    }
+}
@@ -0,0 +1,245 @@
+//! Live deployment progress on terminal, plain log lines otherwise
+
+use lazy_static::lazy_static;
+use log::{Log, Metadata, Record};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+    io::Write,
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+/// Objects in flight, which are listed, others are only counted
+const MAX_LISTED: usize = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stage {
+    Evaluate,
+    HelmRender,
+    Discovery,
+    DryRun,
+    Apply,
+    Prune,
+    Wait,
+}
+
+impl Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Evaluate => "evaluate",
+            Self::HelmRender => "helm render",
+            Self::Discovery => "discovery",
+            Self::DryRun => "dry-run",
+            Self::Apply => "apply",
+            Self::Prune => "prune",
+            Self::Wait => "wait",
+        })
+    }
+}
+
+struct State {
+    live: bool,
+    started: Instant,
+    stage: Option<Stage>,
+    stage_started: Instant,
+    done: usize,
+    total: usize,
+    in_flight: Vec<String>,
+    counts: BTreeMap<&'static str, usize>,
+    /// Lines of progress, which are currently on screen
+    drawn: usize,
+}
+
+lazy_static! {
+    static ref STATE: Mutex<State> = Mutex::new(State {
+        live: false,
+        started: Instant::now(),
+        stage: None,
+        stage_started: Instant::now(),
+        done: 0,
+        total: 0,
+        in_flight: Vec::new(),
+        counts: BTreeMap::new(),
+        drawn: 0,
+    });
+}
+
+impl State {
+    fn lines(&self) -> Vec<String> {
+        let mut out = Vec::new();
+        let stage = match self.stage {
+            Some(stage) => stage,
+            None => return out,
+        };
+        let mut header = format!("{}", stage);
+        if self.total != 0 {
+            header.push_str(&format!(" {}/{}", self.done, self.total));
+        }
+        header.push_str(&format!(
+            " · {:.1}s, {:.1}s total",
+            self.stage_started.elapsed().as_secs_f32(),
+            self.started.elapsed().as_secs_f32()
+        ));
+        out.push(header);
+        if !self.counts.is_empty() {
+            out.push(format!(
+                "  {}",
+                self.counts
+                    .iter()
+                    .map(|(result, count)| format!("{} {}", count, result))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        for object in self.in_flight.iter().take(MAX_LISTED) {
+            out.push(format!("  > {}", object));
+        }
+        if self.in_flight.len() > MAX_LISTED {
+            out.push(format!("  and {} more", self.in_flight.len() - MAX_LISTED));
+        }
+        out
+    }
+
+    fn clear(&mut self, out: &mut impl Write) {
+        if self.drawn != 0 {
+            let _ = write!(out, "\x1b[{}A\x1b[J", self.drawn);
+            self.drawn = 0;
+        }
+    }
+
+    fn redraw(&mut self) {
+        if !self.live {
+            return;
+        }
+        let stderr = std::io::stderr();
+        let mut out = stderr.lock();
+        self.clear(&mut out);
+        let lines = self.lines();
+        // Autowrap is disabled, so every line takes exactly one row
+        let _ = write!(out, "\x1b[?7l");
+        for line in &lines {
+            let _ = writeln!(out, "{}", line);
+        }
+        let _ = write!(out, "\x1b[?7h");
+        let _ = out.flush();
+        self.drawn = lines.len();
+    }
+}
+
+/// Enable live display, should be called once
+pub fn init(live: bool) {
+    let mut state = STATE.lock().unwrap();
+    state.live = live;
+    state.started = Instant::now();
+    if live {
+        thread::spawn(|| loop {
+            thread::sleep(REDRAW_INTERVAL);
+            let mut state = STATE.lock().unwrap();
+            if !state.live {
+                break;
+            }
+            state.redraw();
+        });
+    }
+}
+
+/// Enter stage, which consists of `total` objects, or 0 if unknown
+pub fn stage(stage: Stage, total: usize) {
+    let mut state = STATE.lock().unwrap();
+    state.stage = Some(stage);
+    state.stage_started = Instant::now();
+    state.done = 0;
+    state.total = total;
+    state.in_flight.clear();
+    if state.live {
+        state.redraw();
+        return;
+    }
+    // Logger locks state too
+    drop(state);
+    if total != 0 {
+        log::info!("{} of {} objects", stage, total);
+    } else {
+        log::info!("{}", stage);
+    }
+}
+
+/// Object is being processed in current stage
+pub fn start(object: &impl Display) {
+    let mut state = STATE.lock().unwrap();
+    state.in_flight.push(object.to_string());
+    state.redraw();
+}
+
+/// Object is processed in current stage
+pub fn finished(object: &impl Display) {
+    let mut state = STATE.lock().unwrap();
+    let object = object.to_string();
+    if let Some(idx) = state.in_flight.iter().position(|o| o == &object) {
+        state.in_flight.remove(idx);
+    }
+    state.done += 1;
+    state.redraw();
+}
+
+/// Count final result of object, i.e `created`
+pub fn count(result: &'static str, object: &impl Display) {
+    let mut state = STATE.lock().unwrap();
+    *state.counts.entry(result).or_insert(0) += 1;
+    if state.live {
+        state.redraw();
+        return;
+    }
+    drop(state);
+    log::debug!("{} {}", result, object);
+}
+
+/// Print text to stderr above live display, i.e logs of failed task
+pub fn print(text: &str) {
+    let mut state = STATE.lock().unwrap();
+    let stderr = std::io::stderr();
+    let mut out = stderr.lock();
+    if state.live {
+        state.clear(&mut out);
+    }
+    let _ = writeln!(out, "{}", text.trim_end());
+    drop(out);
+    state.redraw();
+}
+
+/// Remove live display from screen, output after this call is not disturbed
+pub fn finish() {
+    let mut state = STATE.lock().unwrap();
+    let stderr = std::io::stderr();
+    state.clear(&mut stderr.lock());
+    state.live = false;
+}
+
+/// Logger, which prints records above live display
+pub struct Logger<L>(pub L);
+
+impl<L: Log> Log for Logger<L> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.0.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.0.enabled(record.metadata()) {
+            return;
+        }
+        let mut state = STATE.lock().unwrap();
+        if state.live {
+            let stderr = std::io::stderr();
+            state.clear(&mut stderr.lock());
+        }
+        self.0.log(record);
+        state.redraw();
+    }
+
+    fn flush(&self) {
+        self.0.flush()
+    }
+}
@@ -0,0 +1,158 @@
+//! Locations of rendered objects in deployment source
+
+use jrsonnet_parser::{BinaryOpType, Expr, FieldMember, FieldName, LocExpr, Member, ObjBody};
+use serde_json::Value;
+use std::fmt::{self, Display};
+
+/// Step of path in rendered output
+#[derive(Clone, Debug, PartialEq)]
+pub enum Step {
+    Field(String),
+    Index(usize),
+}
+
+/// Path of object in rendered output, i.e `.app.deployment`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OutputPath(pub Vec<Step>);
+
+impl OutputPath {
+    pub fn join(&self, step: Step) -> Self {
+        let mut out = self.clone();
+        out.0.push(step);
+        out
+    }
+}
+
+impl Display for OutputPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, ".");
+        }
+        for step in &self.0 {
+            match step {
+                Step::Field(field) => write!(f, "{}", field_accessor(field))?,
+                Step::Index(idx) => write!(f, "[{}]", idx)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Jsonnet accessor of field, i.e `.name` or `["app.kubernetes.io/name"]`
+pub fn field_accessor(field: &str) -> String {
+    let mut chars = field.chars();
+    let is_ident = chars
+        .next()
+        .map(|c| c.is_ascii_alphabetic() || c == '_')
+        .unwrap_or(false)
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if is_ident {
+        format!(".{}", field)
+    } else {
+        format!("[{}]", Value::String(field.to_owned()))
+    }
+}
+
+/// Expression, which defines value of step in literal object or array
+fn find_step<'a>(expr: &'a LocExpr, step: &Step) -> Option<&'a LocExpr> {
+    match (&*expr.0, step) {
+        (Expr::Parened(inner), _) | (Expr::LocalExpr(_, inner), _) => find_step(inner, step),
+        // Top level arguments
+        (Expr::Function(_, body), _) => find_step(body, step),
+        (Expr::Obj(ObjBody::MemberList(members)), Step::Field(name)) => {
+            // Last definition wins
+            members.iter().rev().find_map(|member| match member {
+                Member::Field(FieldMember {
+                    name: FieldName::Fixed(field),
+                    value,
+                    ..
+                }) if &**field == name.as_str() => Some(value),
+                _ => None,
+            })
+        }
+        (Expr::BinaryOp(lhs, BinaryOpType::Add, rhs), Step::Field(_)) => {
+            find_step(rhs, step).or_else(|| find_step(lhs, step))
+        }
+        (Expr::Arr(items), Step::Index(idx)) => items.get(*idx),
+        _ => None,
+    }
+}
+
+/// Line of expression, which defines object at path
+///
+/// Only literal objects and arrays are followed, for computed and imported objects
+/// line of the nearest enclosing member is returned
+pub fn find_line(source: &str, root: &LocExpr, path: &OutputPath) -> Option<usize> {
+    let mut expr = root;
+    let mut found = None;
+    for step in &path.0 {
+        expr = match find_step(expr, step) {
+            Some(expr) => expr,
+            None => break,
+        };
+        found = expr.1.as_ref().map(|location| location.1);
+    }
+    let offset = found?;
+    Some(source[..offset.min(source.len())].matches('\n').count() + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jrsonnet_parser::{parse, ParserSettings};
+    use std::{path::PathBuf, rc::Rc};
+
+    #[test]
+    fn lines() {
+        let source = "function(prod=false) {
+  local app = 'app',
+  app: {
+    deployment: {
+      kind: 'Deployment',
+    },
+    jobs: [
+      {},
+      import 'job.jsonnet',
+    ],
+  },
+}
+";
+        let expr = parse(
+            source,
+            &ParserSettings {
+                loc_data: true,
+                file_name: Rc::new(PathBuf::from("main.jsonnet")),
+            },
+        )
+        .unwrap();
+        let line = |path: Vec<Step>| find_line(source, &expr, &OutputPath(path));
+
+        let field = |name: &str| Step::Field(name.to_owned());
+        assert_eq!(line(vec![field("app"), field("deployment")]), Some(4));
+        assert_eq!(
+            line(vec![field("app"), field("jobs"), Step::Index(1)]),
+            Some(9)
+        );
+        // Nearest enclosing member
+        assert_eq!(
+            line(vec![
+                field("app"),
+                field("jobs"),
+                Step::Index(1),
+                field("items")
+            ]),
+            Some(9)
+        );
+        assert_eq!(line(vec![]), None);
+    }
+
+    #[test]
+    fn paths() {
+        let path = OutputPath::default()
+            .join(Step::Field("app".to_owned()))
+            .join(Step::Field("app.kubernetes.io/name".to_owned()))
+            .join(Step::Index(0));
+        assert_eq!(path.to_string(), r#".app["app.kubernetes.io/name"][0]"#);
+        assert_eq!(OutputPath::default().to_string(), ".");
+    }
+}